@@ -39,6 +39,11 @@ pub struct CfgOptions {
         target_os = "unknown"
     ))]
     pub windows_interception_mouse_hwid: Option<[u8; HWID_ARR_SZ]>,
+    #[cfg(any(
+        all(feature = "interception_driver", target_os = "windows"),
+        target_os = "unknown"
+    ))]
+    pub windows_interception_swap_nav_buttons: bool,
     #[cfg(any(target_os = "macos", target_os = "unknown"))]
     pub macos_dev_names_include: Option<Vec<String>>,
 }
@@ -79,6 +84,11 @@ impl Default for CfgOptions {
                 target_os = "unknown"
             ))]
             windows_interception_mouse_hwid: None,
+            #[cfg(any(
+                all(feature = "interception_driver", target_os = "windows"),
+                target_os = "unknown"
+            ))]
+            windows_interception_swap_nav_buttons: false,
             #[cfg(any(target_os = "macos", target_os = "unknown"))]
             macos_dev_names_include: None,
         }
@@ -275,6 +285,16 @@ pub fn parse_defcfg(expr: &[SExpr]) -> Result<CfgOptions> {
                             cfg.linux_continue_if_no_devs_found = parse_defcfg_val_bool(val, label)?
                         }
                     }
+                    "windows-interception-swap-nav-buttons" => {
+                        #[cfg(any(
+                            all(feature = "interception_driver", target_os = "windows"),
+                            target_os = "unknown"
+                        ))]
+                        {
+                            cfg.windows_interception_swap_nav_buttons =
+                                parse_defcfg_val_bool(val, label)?
+                        }
+                    }
                     "movemouse-smooth-diagonals" => {
                         cfg.movemouse_smooth_diagonals = parse_defcfg_val_bool(val, label)?
                     }
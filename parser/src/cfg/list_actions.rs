@@ -37,6 +37,10 @@ pub const MWHEEL_UP: &str = "mwheel-up";
 pub const MWHEEL_DOWN: &str = "mwheel-down";
 pub const MWHEEL_LEFT: &str = "mwheel-left";
 pub const MWHEEL_RIGHT: &str = "mwheel-right";
+pub const MWHEEL_UP_LEFT: &str = "mwheel-up-left";
+pub const MWHEEL_UP_RIGHT: &str = "mwheel-up-right";
+pub const MWHEEL_DOWN_LEFT: &str = "mwheel-down-left";
+pub const MWHEEL_DOWN_RIGHT: &str = "mwheel-down-right";
 pub const MOVEMOUSE_UP: &str = "movemouse-up";
 pub const MOVEMOUSE_DOWN: &str = "movemouse-down";
 pub const MOVEMOUSE_LEFT: &str = "movemouse-left";
@@ -62,7 +66,7 @@ pub const UNMOD: &str = "unmod";
 pub const UNSHIFT: &str = "unshift";
 
 pub fn is_list_action(ac: &str) -> bool {
-    const LIST_ACTIONS: [&str; 58] = [
+    const LIST_ACTIONS: [&str; 62] = [
         LAYER_SWITCH,
         LAYER_TOGGLE,
         LAYER_WHILE_HELD,
@@ -98,6 +102,10 @@ pub fn is_list_action(ac: &str) -> bool {
         MWHEEL_DOWN,
         MWHEEL_LEFT,
         MWHEEL_RIGHT,
+        MWHEEL_UP_LEFT,
+        MWHEEL_UP_RIGHT,
+        MWHEEL_DOWN_LEFT,
+        MWHEEL_DOWN_RIGHT,
         MOVEMOUSE_UP,
         MOVEMOUSE_DOWN,
         MOVEMOUSE_LEFT,
@@ -1124,6 +1124,16 @@ fn parse_action_atom(ac_span: &Spanned<String>, s: &ParsedState) -> Result<&'sta
                 s.a.sref(s.a.sref_slice(CustomAction::Mouse(Btn::Backward))),
             )))
         }
+        "mb6" | "mousebutton6" => {
+            return Ok(s.a.sref(Action::Custom(
+                s.a.sref(s.a.sref_slice(CustomAction::Mouse(Btn::Mouse6))),
+            )))
+        }
+        "mb7" | "mousebutton7" => {
+            return Ok(s.a.sref(Action::Custom(
+                s.a.sref(s.a.sref_slice(CustomAction::Mouse(Btn::Mouse7))),
+            )))
+        }
         "mltp" | "mousetapleft" => {
             return Ok(s.a.sref(Action::Custom(
                 s.a.sref(s.a.sref_slice(CustomAction::MouseTap(Btn::Left))),
@@ -1149,6 +1159,16 @@ fn parse_action_atom(ac_span: &Spanned<String>, s: &ParsedState) -> Result<&'sta
                 s.a.sref(s.a.sref_slice(CustomAction::MouseTap(Btn::Backward))),
             )))
         }
+        "mb6tp" | "mousetapbutton6" => {
+            return Ok(s.a.sref(Action::Custom(
+                s.a.sref(s.a.sref_slice(CustomAction::MouseTap(Btn::Mouse6))),
+            )))
+        }
+        "mb7tp" | "mousetapbutton7" => {
+            return Ok(s.a.sref(Action::Custom(
+                s.a.sref(s.a.sref_slice(CustomAction::MouseTap(Btn::Mouse7))),
+            )))
+        }
         "mwu" | "mousewheelup" => {
             return Ok(s.a.sref(Action::Custom(s.a.sref(s.a.sref_slice(
                 CustomAction::MWheelNotch {
@@ -1278,6 +1298,10 @@ fn parse_action_list(ac: &[SExpr], s: &ParsedState) -> Result<&'static KanataAct
         MWHEEL_DOWN => parse_mwheel(&ac[1..], MWheelDirection::Down, s),
         MWHEEL_LEFT => parse_mwheel(&ac[1..], MWheelDirection::Left, s),
         MWHEEL_RIGHT => parse_mwheel(&ac[1..], MWheelDirection::Right, s),
+        MWHEEL_UP_LEFT => parse_mwheel_diagonal(&ac[1..], MWheelDiagonal::UpLeft, s),
+        MWHEEL_UP_RIGHT => parse_mwheel_diagonal(&ac[1..], MWheelDiagonal::UpRight, s),
+        MWHEEL_DOWN_LEFT => parse_mwheel_diagonal(&ac[1..], MWheelDiagonal::DownLeft, s),
+        MWHEEL_DOWN_RIGHT => parse_mwheel_diagonal(&ac[1..], MWheelDiagonal::DownRight, s),
         MOVEMOUSE_UP => parse_move_mouse(&ac[1..], MoveDirection::Up, s),
         MOVEMOUSE_DOWN => parse_move_mouse(&ac[1..], MoveDirection::Down, s),
         MOVEMOUSE_LEFT => parse_move_mouse(&ac[1..], MoveDirection::Left, s),
@@ -2415,6 +2439,34 @@ fn parse_mwheel(
     )))))
 }
 
+/// A diagonal scroll is a vertical and a horizontal scroll that are held together, so the
+/// repeated scrolling of each axis keeps working as it does for the single directions.
+fn parse_mwheel_diagonal(
+    ac_params: &[SExpr],
+    diagonal: MWheelDiagonal,
+    s: &ParsedState,
+) -> Result<&'static KanataAction> {
+    const ERR_MSG: &str = "mwheel expects 2 parameters: <interval (ms)> <distance>";
+    if ac_params.len() != 2 {
+        bail!("{ERR_MSG}, found {}", ac_params.len());
+    }
+    let interval = parse_non_zero_u16(&ac_params[0], s, "interval")?;
+    let distance = parse_distance(&ac_params[1], s, "distance")?;
+    let (vertical, horizontal) = diagonal.split();
+    Ok(s.a.sref(Action::Custom(s.a.sref(s.a.sref_vec(vec![
+        s.a.sref(CustomAction::MWheel {
+            direction: vertical,
+            interval,
+            distance,
+        }),
+        s.a.sref(CustomAction::MWheel {
+            direction: horizontal,
+            interval,
+            distance,
+        }),
+    ])))))
+}
+
 fn parse_move_mouse(
     ac_params: &[SExpr],
     direction: MoveDirection,
@@ -1326,6 +1326,7 @@ fn parse_all_defcfg() {
   linux-x11-repeat-delay-rate 400,50
   windows-altgr add-lctl-release
   windows-interception-mouse-hwid "70, 0, 60, 0"
+  windows-interception-swap-nav-buttons yes
 )
 (defsrc a)
 (deflayer base a)
@@ -1459,3 +1460,62 @@ fn parse_cmd() {
     )
     .expect("succeeds");
 }
+
+#[test]
+fn parse_extra_mouse_buttons() {
+    let s = ParsedState::default();
+    for (name, action) in [
+        ("mb6", CustomAction::Mouse(Btn::Mouse6)),
+        ("mb7", CustomAction::Mouse(Btn::Mouse7)),
+        ("mousebutton6", CustomAction::Mouse(Btn::Mouse6)),
+        ("mb6tp", CustomAction::MouseTap(Btn::Mouse6)),
+        ("mb7tp", CustomAction::MouseTap(Btn::Mouse7)),
+        ("mousetapbutton7", CustomAction::MouseTap(Btn::Mouse7)),
+    ] {
+        let expr = SExpr::Atom(Spanned::new(name.into(), Span::default()));
+        assert_eq!(
+            parse_action(&expr, &s).unwrap(),
+            &Action::Custom(&&[&action][..]),
+            "{name}"
+        );
+    }
+}
+
+#[test]
+fn parse_mwheel_diagonals() {
+    let s = ParsedState::default();
+    let atom = |a: &str| SExpr::Atom(Spanned::new(a.into(), Span::default()));
+    for (name, vertical, horizontal) in [
+        ("mwheel-up-left", MWheelDirection::Up, MWheelDirection::Left),
+        (
+            "mwheel-up-right",
+            MWheelDirection::Up,
+            MWheelDirection::Right,
+        ),
+        (
+            "mwheel-down-left",
+            MWheelDirection::Down,
+            MWheelDirection::Left,
+        ),
+        (
+            "mwheel-down-right",
+            MWheelDirection::Down,
+            MWheelDirection::Right,
+        ),
+    ] {
+        let expr = SExpr::List(Spanned::new(
+            vec![atom(name), atom("50"), atom("120")],
+            Span::default(),
+        ));
+        let mwheel = |direction| CustomAction::MWheel {
+            direction,
+            interval: 50,
+            distance: 120,
+        };
+        assert_eq!(
+            parse_action(&expr, &s).unwrap(),
+            &Action::Custom(&&[&mwheel(vertical), &mwheel(horizontal)][..]),
+            "{name}"
+        );
+    }
+}
@@ -80,6 +80,9 @@ pub enum Btn {
     Mid,
     Forward,
     Backward,
+    /// Extra buttons present on some mice, past forward and backward.
+    Mouse6,
+    Mouse7,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -112,6 +115,27 @@ pub enum MWheelDirection {
     Right,
 }
 
+/// Diagonal scroll, which is sent as a vertical and a horizontal scroll together.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MWheelDiagonal {
+    UpLeft,
+    UpRight,
+    DownLeft,
+    DownRight,
+}
+
+impl MWheelDiagonal {
+    /// The vertical and horizontal directions that make up the diagonal.
+    pub fn split(self) -> (MWheelDirection, MWheelDirection) {
+        match self {
+            MWheelDiagonal::UpLeft => (MWheelDirection::Up, MWheelDirection::Left),
+            MWheelDiagonal::UpRight => (MWheelDirection::Up, MWheelDirection::Right),
+            MWheelDiagonal::DownLeft => (MWheelDirection::Down, MWheelDirection::Left),
+            MWheelDiagonal::DownRight => (MWheelDirection::Down, MWheelDirection::Right),
+        }
+    }
+}
+
 impl TryFrom<OsCode> for MWheelDirection {
     type Error = ();
     fn try_from(value: OsCode) -> Result<Self, Self::Error> {
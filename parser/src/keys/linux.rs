@@ -768,6 +768,8 @@ impl From<Btn> for OsCode {
             Btn::Mid => OsCode::BTN_MIDDLE,
             Btn::Forward => OsCode::BTN_EXTRA,
             Btn::Backward => OsCode::BTN_SIDE,
+            Btn::Mouse6 => OsCode::BTN_FORWARD,
+            Btn::Mouse7 => OsCode::BTN_BACK,
         }
     }
 }
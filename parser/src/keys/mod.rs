@@ -301,6 +301,15 @@ pub fn str_to_oscode(s: &str) -> Option<OsCode> {
         "mwl" | "mousewheelleft" => OsCode::MouseWheelLeft,
         #[cfg(any(target_os = "linux", target_os = "unknown", feature = "interception_driver"))]
         "mwr" | "mousewheelright" => OsCode::MouseWheelRight,
+        // NOTE: these are interception-only since only the interception read loop produces them
+        #[cfg(any(target_os = "unknown", feature = "interception_driver"))]
+        "mmu" | "mousemoveup" => OsCode::MouseMoveUp,
+        #[cfg(any(target_os = "unknown", feature = "interception_driver"))]
+        "mmd" | "mousemovedown" => OsCode::MouseMoveDown,
+        #[cfg(any(target_os = "unknown", feature = "interception_driver"))]
+        "mml" | "mousemoveleft" => OsCode::MouseMoveLeft,
+        #[cfg(any(target_os = "unknown", feature = "interception_driver"))]
+        "mmr" | "mousemoveright" => OsCode::MouseMoveRight,
 
         "hmpg" | "homepage" => OsCode::KEY_HOMEPAGE,
         "mdia" | "media" => OsCode::KEY_MEDIA,
@@ -1088,6 +1097,12 @@ pub enum OsCode {
     MouseWheelLeft = 747,
     MouseWheelRight = 748,
 
+    // Mouse movement read from an intercepted mouse, same as the mouse wheel events above.
+    MouseMoveUp = 749,
+    MouseMoveDown = 750,
+    MouseMoveLeft = 751,
+    MouseMoveRight = 752,
+
     KEY_MAX = 767,
 }
 
@@ -266,7 +266,7 @@ impl Kanata {
             }
         };
 
-        let kbd_out = match KbdOut::new(
+        let mut kbd_out = match KbdOut::new(
             #[cfg(target_os = "linux")]
             &args.symlink_path,
         ) {
@@ -294,7 +294,7 @@ impl Kanata {
             );
         }
 
-        update_kbd_out(&cfg.items, &kbd_out)?;
+        update_kbd_out(&cfg.items, &mut kbd_out)?;
 
         #[cfg(target_os = "windows")]
         set_win_altgr_behaviour(cfg.items.windows_altgr);
@@ -368,7 +368,9 @@ impl Kanata {
                 bail!("failed to parse config file");
             }
         };
-        update_kbd_out(&cfg.items, &self.kbd_out)?;
+        #[cfg(all(feature = "interception_driver", target_os = "windows"))]
+        self.kbd_out.reset()?;
+        update_kbd_out(&cfg.items, &mut self.kbd_out)?;
         #[cfg(target_os = "windows")]
         set_win_altgr_behaviour(cfg.items.windows_altgr);
         self.sequence_backtrack_modcancel = cfg.items.sequence_backtrack_modcancel;
@@ -458,6 +460,8 @@ impl Kanata {
             self.tick_sequence_state()?;
             self.tick_dynamic_macro_state()?;
             self.tick_idle_timeout();
+            #[cfg(all(feature = "interception_driver", target_os = "windows"))]
+            self.kbd_out.tick()?;
 
             self.prev_keys.clear();
             self.prev_keys.append(&mut self.cur_keys);
@@ -1766,6 +1770,11 @@ impl Kanata {
     }
 
     pub fn is_idle(&self) -> bool {
+        // Scheduled output, such as the release of a held key, is only sent by ticks.
+        #[cfg(all(feature = "interception_driver", target_os = "windows"))]
+        if self.kbd_out.has_pending_output() {
+            return false;
+        }
         let pressed_keys_means_not_idle =
             !self.waiting_for_idle.is_empty() || self.live_reload_requested;
         self.layout.b().queue.is_empty()
@@ -1876,12 +1885,14 @@ fn check_for_exit(event: &KeyEvent) {
     }
 }
 
-fn update_kbd_out(_cfg: &CfgOptions, _kbd_out: &KbdOut) -> Result<()> {
+fn update_kbd_out(_cfg: &CfgOptions, _kbd_out: &mut KbdOut) -> Result<()> {
     #[cfg(target_os = "linux")]
     {
         _kbd_out.update_unicode_termination(_cfg.linux_unicode_termination);
         _kbd_out.update_unicode_u_code(_cfg.linux_unicode_u_code);
     }
+    #[cfg(all(feature = "interception_driver", target_os = "windows"))]
+    _kbd_out.set_swap_nav_buttons(_cfg.windows_interception_swap_nav_buttons);
     Ok(())
 }
 
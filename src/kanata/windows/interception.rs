@@ -1,7 +1,7 @@
 use anyhow::{anyhow, Result};
 use kanata_interception as ic;
 use parking_lot::Mutex;
-use std::sync::mpsc::SyncSender as Sender;
+use std::sync::mpsc::{SyncSender as Sender, TrySendError};
 use std::sync::Arc;
 
 use super::PRESSED_KEYS;
@@ -20,19 +20,29 @@ impl Kanata {
         }; 32];
 
         let mouse_to_intercept_hwid: Option<[u8; HWID_ARR_SZ]> = kanata.lock().intercept_mouse_hwid;
-        if mouse_to_intercept_hwid.is_some() {
-            intrcptn.set_filter(
-                ic::is_mouse,
-                ic::Filter::MouseFilter(ic::MouseState::all() & (!ic::MouseState::MOVE)),
-            );
-        }
+        let mut mouse_filter = None;
         let mut is_dev_interceptable: HashMap<ic::Device, bool> = HashMap::default();
 
         loop {
+            if mouse_to_intercept_hwid.is_some() {
+                // Checked on every wakeup, since a live reload can change whether movement is
+                // remapped.
+                let filter = mouse_filter_for_mapped_keys();
+                if mouse_filter != Some(filter) {
+                    log::debug!("setting mouse filter {filter:?}");
+                    intrcptn.set_filter(ic::is_mouse, ic::Filter::MouseFilter(filter));
+                    mouse_filter = Some(filter);
+                }
+            }
             let dev = intrcptn.wait();
             if dev > 0 {
                 let num_strokes = intrcptn.receive(dev, &mut strokes) as usize;
                 for i in 0..num_strokes {
+                    if is_passed_through(&strokes[i], mouse_to_intercept_hwid.is_some()) {
+                        log::trace!("passing through {:?}", strokes[i]);
+                        intrcptn.send(dev, &strokes[i..i + 1]);
+                        continue;
+                    }
                     let mut key_event = match strokes[i] {
                         ic::Stroke::Keyboard { state, .. } => {
                             log::debug!("got stroke {:?}", strokes[i]);
@@ -50,26 +60,48 @@ impl Kanata {
                             };
                             KeyEvent { code, value }
                         }
-                        ic::Stroke::Mouse { state, rolling, .. } => {
-                            if let Some(hwid) = mouse_to_intercept_hwid {
-                                log::trace!("checking mouse stroke {:?}", strokes[i]);
-                                if let Some(event) = mouse_state_to_event(
-                                    dev,
-                                    &hwid,
-                                    state,
-                                    rolling,
-                                    &intrcptn,
-                                    &mut is_dev_interceptable,
-                                ) {
-                                    event
-                                } else {
+                        ic::Stroke::Mouse { .. } => {
+                            let hwid = match mouse_to_intercept_hwid {
+                                Some(hwid) => hwid,
+                                None => {
                                     intrcptn.send(dev, &strokes[i..i + 1]);
                                     continue;
                                 }
-                            } else {
+                            };
+                            log::trace!("checking mouse stroke {:?}", strokes[i]);
+                            if !is_mouse_interceptable(
+                                dev,
+                                &hwid,
+                                &intrcptn,
+                                &mut is_dev_interceptable,
+                            ) {
                                 intrcptn.send(dev, &strokes[i..i + 1]);
                                 continue;
                             }
+                            if let Some((dx, dy)) = InputEvent(strokes[i]).mouse_move() {
+                                let (events, rest) = mouse_move_to_events(dx, dy);
+                                if rest != (0, 0) {
+                                    // Movement on an axis that is not remapped still reaches
+                                    // the OS.
+                                    let rest =
+                                        InputEvent(strokes[i]).with_mouse_move(rest.0, rest.1);
+                                    intrcptn.send(dev, &[rest.0]);
+                                }
+                                for event in events {
+                                    send_mouse_move(&tx, event)?;
+                                }
+                                continue;
+                            }
+                            match mouse_state_to_event(
+                                InputEvent(strokes[i]).mouse_btn(),
+                                InputEvent(strokes[i]).mouse_wheel(),
+                            ) {
+                                Some(event) => event,
+                                None => {
+                                    intrcptn.send(dev, &strokes[i..i + 1]);
+                                    continue;
+                                }
+                            }
                         }
                     };
                     check_for_exit(&key_event);
@@ -100,15 +132,32 @@ impl Kanata {
     }
 }
 
-fn mouse_state_to_event(
+/// Only ask for movement if it is remapped, since there are far more movement strokes than any
+/// other kind.
+fn mouse_filter_for_mapped_keys() -> ic::MouseState {
+    let mut mouse_filter = ic::MouseState::all();
+    let mapped_keys = MAPPED_KEYS.lock();
+    if ![
+        OsCode::MouseMoveUp,
+        OsCode::MouseMoveDown,
+        OsCode::MouseMoveLeft,
+        OsCode::MouseMoveRight,
+    ]
+    .iter()
+    .any(|osc| mapped_keys.contains(osc))
+    {
+        mouse_filter.remove(ic::MouseState::MOVE);
+    }
+    mouse_filter
+}
+
+fn is_mouse_interceptable(
     input_dev: ic::Device,
     allowed_hwid: &[u8; HWID_ARR_SZ],
-    state: ic::MouseState,
-    rolling: i16,
     intrcptn: &ic::Interception,
     is_dev_interceptable: &mut HashMap<ic::Device, bool>,
-) -> Option<KeyEvent> {
-    if !match is_dev_interceptable.get(&input_dev) {
+) -> bool {
+    match is_dev_interceptable.get(&input_dev) {
         Some(v) => *v,
         None => {
             let mut hwid = [0u8; HWID_ARR_SZ];
@@ -119,81 +168,80 @@ fn mouse_state_to_event(
             is_dev_interceptable.insert(input_dev, dev_is_interceptable);
             dev_is_interceptable
         }
-    } {
-        return None;
     }
+}
 
-    if state.contains(ic::MouseState::RIGHT_BUTTON_DOWN) {
-        Some(KeyEvent {
-            code: OsCode::BTN_RIGHT,
-            value: KeyValue::Press,
-        })
-    } else if state.contains(ic::MouseState::RIGHT_BUTTON_UP) {
-        Some(KeyEvent {
-            code: OsCode::BTN_RIGHT,
-            value: KeyValue::Release,
-        })
-    } else if state.contains(ic::MouseState::LEFT_BUTTON_DOWN) {
-        Some(KeyEvent {
-            code: OsCode::BTN_LEFT,
-            value: KeyValue::Press,
-        })
-    } else if state.contains(ic::MouseState::LEFT_BUTTON_UP) {
-        Some(KeyEvent {
-            code: OsCode::BTN_LEFT,
-            value: KeyValue::Release,
-        })
-    } else if state.contains(ic::MouseState::MIDDLE_BUTTON_DOWN) {
-        Some(KeyEvent {
-            code: OsCode::BTN_MIDDLE,
-            value: KeyValue::Press,
-        })
-    } else if state.contains(ic::MouseState::MIDDLE_BUTTON_UP) {
-        Some(KeyEvent {
-            code: OsCode::BTN_MIDDLE,
-            value: KeyValue::Release,
-        })
-    } else if state.contains(ic::MouseState::BUTTON_4_DOWN) {
-        Some(KeyEvent {
-            code: OsCode::BTN_SIDE,
-            value: KeyValue::Press,
-        })
-    } else if state.contains(ic::MouseState::BUTTON_4_UP) {
-        Some(KeyEvent {
-            code: OsCode::BTN_SIDE,
-            value: KeyValue::Release,
-        })
-    } else if state.contains(ic::MouseState::BUTTON_5_DOWN) {
-        Some(KeyEvent {
-            code: OsCode::BTN_EXTRA,
-            value: KeyValue::Press,
+/// The events for the axes of a relative movement that are remapped, and the movement that is
+/// left on the other axes.
+fn mouse_move_to_events(dx: i32, dy: i32) -> (Vec<KeyEvent>, (i32, i32)) {
+    let mapped_keys = MAPPED_KEYS.lock();
+    let [x, y] =
+        MouseMove::from_axes(dx, dy).map(|mv| mv.filter(|mv| mapped_keys.contains(&mv.oscode())));
+    let rest = (
+        if x.is_some() { 0 } else { dx },
+        if y.is_some() { 0 } else { dy },
+    );
+    let events = [x, y]
+        .into_iter()
+        .flatten()
+        .map(|mv| {
+            log::trace!("mouse moved {mv:?}");
+            KeyEvent {
+                code: mv.oscode(),
+                value: KeyValue::Tap,
+            }
         })
-    } else if state.contains(ic::MouseState::BUTTON_5_UP) {
+        .collect();
+    (events, rest)
+}
+
+/// Movement strokes arrive at the mouse report rate, which can be faster than the processing
+/// loop drains the channel. A movement event that does not fit is dropped, since the next stroke
+/// moves again, rather than ending the event loop.
+fn send_mouse_move(tx: &Sender<KeyEvent>, event: KeyEvent) -> Result<()> {
+    match tx.try_send(event) {
+        Ok(()) => {
+            log::trace!("sent {event:?} to processing loop");
+            Ok(())
+        }
+        Err(TrySendError::Full(event)) => {
+            log::debug!("processing loop is busy, dropping {event:?}");
+            Ok(())
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn mouse_state_to_event(
+    button: Option<(Btn, bool)>,
+    wheel: Option<(MWheelDirection, u16)>,
+) -> Option<KeyEvent> {
+    if let Some((btn, is_up)) = button {
+        let code = match btn {
+            Btn::Left => OsCode::BTN_LEFT,
+            Btn::Right => OsCode::BTN_RIGHT,
+            Btn::Mid => OsCode::BTN_MIDDLE,
+            Btn::Backward => OsCode::BTN_SIDE,
+            Btn::Forward => OsCode::BTN_EXTRA,
+            // Interception only reports five buttons.
+            Btn::Mouse6 | Btn::Mouse7 => return None,
+        };
         Some(KeyEvent {
-            code: OsCode::BTN_EXTRA,
-            value: KeyValue::Release,
+            code,
+            value: match is_up {
+                false => KeyValue::Press,
+                true => KeyValue::Release,
+            },
         })
-    } else if state.contains(ic::MouseState::WHEEL) {
-        let osc = if rolling >= 0 {
-            OsCode::MouseWheelUp
-        } else {
-            OsCode::MouseWheelDown
-        };
-        if MAPPED_KEYS.lock().contains(&osc) {
-            Some(KeyEvent {
-                code: osc,
-                value: KeyValue::Tap,
-            })
-        } else {
-            None
-        }
-    } else if state.contains(ic::MouseState::HWHEEL) {
-        let osc = if rolling >= 0 {
-            OsCode::MouseWheelRight
-        } else {
-            OsCode::MouseWheelLeft
+    } else if let Some((direction, distance)) = wheel {
+        let osc = match direction {
+            MWheelDirection::Up => OsCode::MouseWheelUp,
+            MWheelDirection::Down => OsCode::MouseWheelDown,
+            MWheelDirection::Left => OsCode::MouseWheelLeft,
+            MWheelDirection::Right => OsCode::MouseWheelRight,
         };
         if MAPPED_KEYS.lock().contains(&osc) {
+            log::trace!("mouse wheel {direction:?} {distance}");
             Some(KeyEvent {
                 code: osc,
                 value: KeyValue::Tap,
@@ -66,3 +66,96 @@ impl KeyEvent {
         Self { code, value }
     }
 }
+
+// ------------------ KbdOutput --------------------
+
+use std::io;
+
+/// Mark that kanata stamps on its own Windows output, so that the input side can tell it apart
+/// from hardware input and does not remap it a second time, which would loop when output and
+/// input share a device:
+///
+/// - With interception: the `information` field of every stroke.
+/// - With `SendInput`, used by the llhook backend and for the virtual key and unicode output of
+///   the interception backend: the `dwExtraInfo` field of every input.
+///
+/// The Linux and macOS backends do not stamp their output.
+#[cfg(target_os = "windows")]
+pub const KANATA_SYNTHETIC_MARK: u32 = 0x4b4e_0000; // "KN"
+
+/// Bits of a stamped value that hold [`KANATA_SYNTHETIC_MARK`]. A backend may use the rest for
+/// data of its own, such as a source id or a repeat flag.
+#[cfg(target_os = "windows")]
+pub const KANATA_SYNTHETIC_MARK_MASK: u32 = 0x7fff_0000;
+
+/// Returns true if `extra`, the extra data that a Windows backend read along with an input event,
+/// carries [`KANATA_SYNTHETIC_MARK`].
+#[cfg(target_os = "windows")]
+#[allow(dead_code)] // Only interception checks it; llhook drops all injected input.
+pub fn has_synthetic_mark(extra: u32) -> bool {
+    extra & KANATA_SYNTHETIC_MARK_MASK == KANATA_SYNTHETIC_MARK
+}
+
+use crate::kanata::CalculatedMouseMove;
+use kanata_parser::custom_action::{Btn, MWheelDirection};
+
+/// Output operations that every platform's `KbdOut` provides, so that code can be written
+/// generically over the backend.
+#[allow(dead_code)] // The processing loop still uses the platform's KbdOut directly.
+pub trait KbdOutput {
+    fn write_key(&mut self, key: OsCode, value: KeyValue) -> Result<(), io::Error>;
+    fn write_code(&mut self, code: u32, value: KeyValue) -> Result<(), io::Error>;
+    fn press_key(&mut self, key: OsCode) -> Result<(), io::Error>;
+    fn release_key(&mut self, key: OsCode) -> Result<(), io::Error>;
+    fn send_unicode(&mut self, c: char) -> Result<(), io::Error>;
+    fn click_btn(&mut self, btn: Btn) -> Result<(), io::Error>;
+    fn release_btn(&mut self, btn: Btn) -> Result<(), io::Error>;
+    fn scroll(&mut self, direction: MWheelDirection, distance: u16) -> Result<(), io::Error>;
+    fn move_mouse(&mut self, mv: CalculatedMouseMove) -> Result<(), io::Error>;
+    fn move_mouse_many(&mut self, moves: &[CalculatedMouseMove]) -> Result<(), io::Error>;
+    fn set_mouse(&mut self, x: u16, y: u16) -> Result<(), io::Error>;
+}
+
+// The inherent methods of each platform's KbdOut are the implementation. The `map_err`
+// converts the error type of the interception KbdOut; the other backends already return
+// io::Error, for which it is the identity.
+#[cfg_attr(
+    not(all(feature = "interception_driver", target_os = "windows")),
+    allow(clippy::useless_conversion)
+)]
+#[cfg(any(target_os = "linux", target_os = "windows", target_os = "macos"))]
+impl KbdOutput for KbdOut {
+    fn write_key(&mut self, key: OsCode, value: KeyValue) -> Result<(), io::Error> {
+        KbdOut::write_key(self, key, value).map_err(Into::into)
+    }
+    fn write_code(&mut self, code: u32, value: KeyValue) -> Result<(), io::Error> {
+        KbdOut::write_code(self, code, value).map_err(Into::into)
+    }
+    fn press_key(&mut self, key: OsCode) -> Result<(), io::Error> {
+        KbdOut::press_key(self, key).map_err(Into::into)
+    }
+    fn release_key(&mut self, key: OsCode) -> Result<(), io::Error> {
+        KbdOut::release_key(self, key).map_err(Into::into)
+    }
+    fn send_unicode(&mut self, c: char) -> Result<(), io::Error> {
+        KbdOut::send_unicode(self, c).map_err(Into::into)
+    }
+    fn click_btn(&mut self, btn: Btn) -> Result<(), io::Error> {
+        KbdOut::click_btn(self, btn).map_err(Into::into)
+    }
+    fn release_btn(&mut self, btn: Btn) -> Result<(), io::Error> {
+        KbdOut::release_btn(self, btn).map_err(Into::into)
+    }
+    fn scroll(&mut self, direction: MWheelDirection, distance: u16) -> Result<(), io::Error> {
+        KbdOut::scroll(self, direction, distance).map_err(Into::into)
+    }
+    fn move_mouse(&mut self, mv: CalculatedMouseMove) -> Result<(), io::Error> {
+        KbdOut::move_mouse(self, mv).map_err(Into::into)
+    }
+    fn move_mouse_many(&mut self, moves: &[CalculatedMouseMove]) -> Result<(), io::Error> {
+        KbdOut::move_mouse_many(self, moves).map_err(Into::into)
+    }
+    fn set_mouse(&mut self, x: u16, y: u16) -> Result<(), io::Error> {
+        KbdOut::set_mouse(self, x, y).map_err(Into::into)
+    }
+}
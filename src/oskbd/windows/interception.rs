@@ -1,31 +1,175 @@
 //! Windows interception-based mechanism for reading/writing input events.
 
 use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use kanata_interception::{Interception, KeyState, MouseFlags, MouseState, ScanCode, Stroke};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use rustc_hash::{FxHashMap as HashMap, FxHashSet as HashSet};
 
-use super::OsCodeWrapper;
+use winapi::um::winuser::{VK_CONTROL, VK_MENU, VK_SHIFT};
+
+use kanata_interception::{
+    Device, Interception, KeyState, MouseFlags, MouseState, ScanCode, Stroke,
+};
+
+use super::{OsCodeWrapper, HI_RES_SCROLL_UNITS_IN_LO_RES};
 use crate::kanata::CalculatedMouseMove;
-use crate::oskbd::KeyValue;
+use crate::oskbd::{has_synthetic_mark, KeyValue, KANATA_SYNTHETIC_MARK};
 use kanata_parser::custom_action::*;
 use kanata_parser::keys::*;
 
+/// Bits of the `information` field, on top of [`KANATA_SYNTHETIC_MARK`], that carry the source
+/// id set with [`KbdOut::set_source_id`]. The field is 32 bits and the mark and
+/// [`KANATA_REPEAT_FLAG`] take the upper half, so the id is a `u16`.
+pub const KANATA_SOURCE_ID_MASK: u32 = 0x0000_ffff;
+
+/// Set in the `information` field, on top of [`KANATA_SYNTHETIC_MARK`], for key strokes that are
+/// an auto-repeat rather than a fresh press. Interception has no repeat state, so a repeat is
+/// otherwise an ordinary down stroke.
+pub const KANATA_REPEAT_FLAG: u32 = 0x8000_0000;
+
+fn information(stroke: &Stroke) -> u32 {
+    match stroke {
+        Stroke::Keyboard { information, .. } | Stroke::Mouse { information, .. } => *information,
+    }
+}
+
+/// Returns true if `stroke` was generated by kanata, with any source id.
+pub fn is_synthetic(stroke: &Stroke) -> bool {
+    has_synthetic_mark(information(stroke))
+}
+
+/// Returns true if the read loop should send `stroke` on unchanged instead of processing it:
+/// output of this or another kanata instance, and mouse strokes when `intercepts_mouse` is false.
+/// Processing kanata's own output would remap it a second time.
+pub fn is_passed_through(stroke: &Stroke, intercepts_mouse: bool) -> bool {
+    is_synthetic(stroke) || (matches!(stroke, Stroke::Mouse { .. }) && !intercepts_mouse)
+}
+
+/// Returns the source id of the kanata instance that generated `stroke`, if it was generated
+/// by kanata.
+#[allow(dead_code)] // Decodes the id for tools further down the input stack.
+pub fn source_id(stroke: &Stroke) -> Option<u16> {
+    is_synthetic(stroke).then(|| (information(stroke) & KANATA_SOURCE_ID_MASK) as u16)
+}
+
+/// Returns true if `stroke` is a key repeat generated by kanata.
+pub fn is_repeat(stroke: &Stroke) -> bool {
+    is_synthetic(stroke) && information(stroke) & KANATA_REPEAT_FLAG != 0
+}
+
+/// Error for an [`OsCode`] that has no interception scan code and so cannot be sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OsCodeMappingError(pub OsCode);
+
+impl std::fmt::Display for OsCodeMappingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?} has no interception scan code", self.0)
+    }
+}
+
+impl std::error::Error for OsCodeMappingError {}
+
+impl From<OsCodeMappingError> for io::Error {
+    fn from(e: OsCodeMappingError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidInput, e)
+    }
+}
+
+/// Error from the output methods of [`KbdOut`] and [`KbdOutSender`]. It converts into an
+/// [`io::Error`] of the matching kind for callers that do not need the detail.
+#[derive(Debug)]
+pub enum KbdOutError {
+    /// The key has no interception scan code.
+    Unmapped(OsCodeMappingError),
+    /// An argument is invalid, e.g. an unknown key name or a scroll distance above the maximum.
+    InvalidInput(String),
+    /// The interception driver has no way to send the output, e.g. a sixth mouse button.
+    Unsupported(String),
+    /// Reading or writing the clipboard failed.
+    Clipboard(io::Error),
+    /// The thread of a [`KbdOutSender`] has stopped.
+    ChannelClosed,
+    /// The driver or the OS did not accept the output.
+    Io(io::Error),
+}
+
+impl KbdOutError {
+    /// The kind of the [`io::Error`] that this converts into.
+    pub fn kind(&self) -> io::ErrorKind {
+        match self {
+            KbdOutError::Unmapped(_) | KbdOutError::InvalidInput(_) => io::ErrorKind::InvalidInput,
+            KbdOutError::Unsupported(_) => io::ErrorKind::Unsupported,
+            KbdOutError::ChannelClosed => io::ErrorKind::BrokenPipe,
+            KbdOutError::Clipboard(e) | KbdOutError::Io(e) => e.kind(),
+        }
+    }
+}
+
+impl std::fmt::Display for KbdOutError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KbdOutError::Unmapped(e) => e.fmt(f),
+            KbdOutError::InvalidInput(msg) | KbdOutError::Unsupported(msg) => f.write_str(msg),
+            KbdOutError::Clipboard(e) => write!(f, "clipboard access failed: {e}"),
+            KbdOutError::ChannelClosed => f.write_str("the KbdOut thread has stopped"),
+            KbdOutError::Io(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for KbdOutError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            KbdOutError::Unmapped(e) => Some(e),
+            KbdOutError::Clipboard(e) | KbdOutError::Io(e) => Some(e),
+            KbdOutError::InvalidInput(_)
+            | KbdOutError::Unsupported(_)
+            | KbdOutError::ChannelClosed => None,
+        }
+    }
+}
+
+impl From<io::Error> for KbdOutError {
+    fn from(e: io::Error) -> Self {
+        KbdOutError::Io(e)
+    }
+}
+
+impl From<OsCodeMappingError> for KbdOutError {
+    fn from(e: OsCodeMappingError) -> Self {
+        KbdOutError::Unmapped(e)
+    }
+}
+
+impl From<KbdOutError> for io::Error {
+    fn from(e: KbdOutError) -> Self {
+        match e {
+            KbdOutError::Io(e) => e,
+            e => io::Error::new(e.kind(), e),
+        }
+    }
+}
+
 /// Key event received by the low level keyboard hook.
 #[derive(Debug, Clone, Copy)]
 pub struct InputEvent(pub Stroke);
 
 impl InputEvent {
-    fn from_oscode(code: OsCode, val: KeyValue) -> Self {
-        let mut stroke = Stroke::try_from(OsCodeWrapper(code)).unwrap_or_else(|_| {
-            log::error!("Trying to send unmapped oscode '{code:?}', sending esc instead");
-            Stroke::Keyboard {
-                code: ScanCode::Esc,
-                state: KeyState::empty(),
-                information: 0,
-            }
-        });
+    fn from_oscode(code: OsCode, val: KeyValue) -> Result<Self, OsCodeMappingError> {
+        let mut stroke =
+            Stroke::try_from(OsCodeWrapper(code)).map_err(|_| OsCodeMappingError(code))?;
         match &mut stroke {
-            Stroke::Keyboard { state, .. } => {
+            Stroke::Keyboard {
+                state, information, ..
+            } => {
+                *information = match val {
+                    KeyValue::Repeat => KANATA_SYNTHETIC_MARK | KANATA_REPEAT_FLAG,
+                    _ => KANATA_SYNTHETIC_MARK,
+                };
                 state.set(
                     match val {
                         KeyValue::Press | KeyValue::Repeat => KeyState::DOWN,
@@ -37,11 +181,11 @@ impl InputEvent {
             }
             _ => panic!("expected keyboard stroke"),
         }
-        Self(stroke)
+        Ok(Self(stroke))
     }
 
-    fn from_mouse_btn(btn: Btn, is_up: bool) -> Self {
-        Self(Stroke::Mouse {
+    fn from_mouse_btn(btn: Btn, is_up: bool) -> Result<Self, KbdOutError> {
+        Ok(Self(Stroke::Mouse {
             state: match (btn, is_up) {
                 (Btn::Left, true) => MouseState::LEFT_BUTTON_UP,
                 (Btn::Left, false) => MouseState::LEFT_BUTTON_DOWN,
@@ -53,52 +197,71 @@ impl InputEvent {
                 (Btn::Backward, false) => MouseState::BUTTON_4_DOWN,
                 (Btn::Forward, true) => MouseState::BUTTON_5_UP,
                 (Btn::Forward, false) => MouseState::BUTTON_5_DOWN,
+                // Interception, like Windows raw input, only has states for five buttons.
+                (Btn::Mouse6 | Btn::Mouse7, _) => {
+                    return Err(KbdOutError::Unsupported(format!(
+                        "{btn:?} is not supported by the interception driver"
+                    )))
+                }
             },
             flags: MouseFlags::empty(),
             rolling: 0,
             x: 0,
             y: 0,
-            information: 0,
-        })
+            information: KANATA_SYNTHETIC_MARK,
+        }))
     }
 
-    fn from_mouse_scroll(direction: MWheelDirection, distance: u16) -> Self {
-        Self(Stroke::Mouse {
+    /// Wheel stroke for `distance` in `direction`. With `invert`, the direction is reversed on
+    /// both axes for natural scrolling. Fails if `distance` is above `max_distance` or above
+    /// what a wheel stroke can hold.
+    fn from_mouse_scroll(
+        direction: MWheelDirection,
+        distance: u16,
+        invert: bool,
+        max_distance: u16,
+    ) -> Result<Self, KbdOutError> {
+        // The bound keeps the distance within the range of an `i16`.
+        let distance = bounded_scroll_distance(direction, distance.into(), max_distance)? as i16;
+        let mut rolling = match direction {
+            MWheelDirection::Up | MWheelDirection::Right => distance,
+            MWheelDirection::Down | MWheelDirection::Left => -distance,
+        };
+        if invert {
+            rolling = -rolling;
+        }
+        Ok(Self(Stroke::Mouse {
             state: match direction {
                 MWheelDirection::Up | MWheelDirection::Down => MouseState::WHEEL,
                 MWheelDirection::Left | MWheelDirection::Right => MouseState::HWHEEL,
             },
             flags: MouseFlags::empty(),
-            rolling: match direction {
-                MWheelDirection::Up | MWheelDirection::Right => {
-                    distance.try_into().expect("checked bound of 30000 in cfg")
-                }
-                MWheelDirection::Down | MWheelDirection::Left => {
-                    -(i16::try_from(distance).expect("checked bound of 30000 in cfg"))
-                }
-            },
+            rolling,
             x: 0,
             y: 0,
-            information: 0,
-        })
+            information: KANATA_SYNTHETIC_MARK,
+        }))
     }
 
     fn from_mouse_move(direction: MoveDirection, distance: u16) -> Self {
+        let distance = i32::from(distance);
+        match direction {
+            MoveDirection::Up => Self::from_mouse_move_xy(0, -distance),
+            MoveDirection::Down => Self::from_mouse_move_xy(0, distance),
+            MoveDirection::Left => Self::from_mouse_move_xy(-distance, 0),
+            MoveDirection::Right => Self::from_mouse_move_xy(distance, 0),
+        }
+    }
+
+    /// Relative mouse movement on both axes in a single stroke.
+    fn from_mouse_move_xy(dx: i32, dy: i32) -> Self {
         Self(Stroke::Mouse {
             state: MouseState::MOVE,
             flags: MouseFlags::empty(),
             rolling: 0,
-            x: match direction {
-                MoveDirection::Left => -i32::from(distance),
-                MoveDirection::Right => i32::from(distance),
-                _ => 0,
-            },
-            y: match direction {
-                MoveDirection::Up => -i32::from(distance),
-                MoveDirection::Down => i32::from(distance),
-                _ => 0,
-            },
-            information: 0,
+            x: dx,
+            y: dy,
+            information: KANATA_SYNTHETIC_MARK,
         })
     }
 
@@ -115,14 +278,7 @@ impl InputEvent {
             x_acc += acc_change.0;
             y_acc += acc_change.1;
         }
-        Self(Stroke::Mouse {
-            state: MouseState::MOVE,
-            flags: MouseFlags::empty(),
-            rolling: 0,
-            x: x_acc,
-            y: y_acc,
-            information: 0,
-        })
+        Self::from_mouse_move_xy(x_acc, y_acc)
     }
 
     fn from_mouse_set(x: u16, y: u16) -> Self {
@@ -132,100 +288,3071 @@ impl InputEvent {
             rolling: 0,
             x: i32::from(x),
             y: i32::from(y),
-            information: 0,
+            information: KANATA_SYNTHETIC_MARK,
         })
     }
 }
 
-thread_local! {
-    static INTRCPTN: Interception = Interception::new().expect("interception driver should init: have you completed the interception driver installation?");
+static SUPPORTED_OSCODES: Lazy<Vec<OsCode>> = Lazy::new(|| {
+    (0..=u16::MAX)
+        .filter_map(OsCode::from_u16)
+        .filter(|&code| Stroke::try_from(OsCodeWrapper(code)).is_ok())
+        .collect()
+});
+
+static SUPPORTED_BTNS: Lazy<Vec<Btn>> = Lazy::new(|| {
+    [
+        Btn::Left,
+        Btn::Right,
+        Btn::Mid,
+        Btn::Forward,
+        Btn::Backward,
+        Btn::Mouse6,
+        Btn::Mouse7,
+    ]
+    .into_iter()
+    .filter(|&btn| InputEvent::from_mouse_btn(btn, false).is_ok())
+    .collect()
+});
+
+/// The keys that the interception backend can send, in the order of their codes. Other keys are
+/// dropped by [`KbdOut::write_key`], so config validation can use this to warn about them.
+#[allow(dead_code)] // Config validation does not warn about unsendable keys yet.
+pub fn supported_oscodes() -> &'static [OsCode] {
+    &SUPPORTED_OSCODES
 }
 
-/// Handle for writing keys to the OS.
-pub struct KbdOut {}
+/// The mouse buttons that the interception backend can send. Other buttons are ignored with a
+/// warning.
+#[allow(dead_code)] // Config validation does not warn about unsendable buttons yet.
+pub fn supported_btns() -> &'static [Btn] {
+    &SUPPORTED_BTNS
+}
 
-fn write_interception(event: InputEvent) {
-    let strokes = [event.0];
-    log::debug!("kanata sending {:?} to driver", strokes[0]);
-    INTRCPTN.with(|ic| {
-        match strokes[0] {
-            // Note regarding device numbers:
-            // Keyboard devices are 1-10 and mouse devices are 11-20. Source:
-            // https://github.com/oblitum/Interception/blob/39eecbbc46a52e0402f783b872ef62b0254a896a/library/interception.h#L34
-            Stroke::Keyboard { .. } => {
-                ic.send(1, &strokes[0..1]);
+impl InputEvent {
+    /// The direction and distance of this event if it is a wheel event. Positive distances on the
+    /// horizontal wheel, which tilt wheels also use, are to the right.
+    pub fn mouse_wheel(&self) -> Option<(MWheelDirection, u16)> {
+        match self.0 {
+            Stroke::Mouse { state, rolling, .. } => {
+                let direction = match (
+                    state.contains(MouseState::WHEEL),
+                    state.contains(MouseState::HWHEEL),
+                    rolling >= 0,
+                ) {
+                    (true, _, true) => MWheelDirection::Up,
+                    (true, _, false) => MWheelDirection::Down,
+                    (false, true, true) => MWheelDirection::Right,
+                    (false, true, false) => MWheelDirection::Left,
+                    (false, false, _) => return None,
+                };
+                Some((direction, rolling.unsigned_abs()))
             }
-            Stroke::Mouse { .. } => {
-                ic.send(11, &strokes[0..1]);
+            Stroke::Keyboard { .. } => None,
+        }
+    }
+
+    /// The button and whether it was released, if this is a mouse button event. This is the
+    /// inverse of [`InputEvent::from_mouse_btn`]. A stroke can carry several button transitions;
+    /// only the first one, in the order right, left, middle, 4, 5, is returned.
+    pub fn mouse_btn(&self) -> Option<(Btn, bool)> {
+        const BUTTONS: [(MouseState, Btn, bool); 10] = [
+            (MouseState::RIGHT_BUTTON_DOWN, Btn::Right, false),
+            (MouseState::RIGHT_BUTTON_UP, Btn::Right, true),
+            (MouseState::LEFT_BUTTON_DOWN, Btn::Left, false),
+            (MouseState::LEFT_BUTTON_UP, Btn::Left, true),
+            (MouseState::MIDDLE_BUTTON_DOWN, Btn::Mid, false),
+            (MouseState::MIDDLE_BUTTON_UP, Btn::Mid, true),
+            (MouseState::BUTTON_4_DOWN, Btn::Backward, false),
+            (MouseState::BUTTON_4_UP, Btn::Backward, true),
+            (MouseState::BUTTON_5_DOWN, Btn::Forward, false),
+            (MouseState::BUTTON_5_UP, Btn::Forward, true),
+        ];
+        match self.0 {
+            Stroke::Mouse { state, .. } => BUTTONS
+                .iter()
+                .find(|(bit, _, _)| state.contains(*bit))
+                .map(|(_, btn, is_up)| (*btn, *is_up)),
+            Stroke::Keyboard { .. } => None,
+        }
+    }
+
+    /// The movement carried by this event if it is a relative mouse move, as `(dx, dy)`.
+    pub fn mouse_move(&self) -> Option<(i32, i32)> {
+        match self.0 {
+            Stroke::Mouse {
+                state, flags, x, y, ..
+            } if state.difference(MouseState::MOVE).is_empty()
+                && !flags.contains(MouseFlags::MOVE_ABSOLUTE)
+                && (x, y) != (0, 0) =>
+            {
+                Some((x, y))
             }
+            _ => None,
         }
-    })
+    }
+
+    /// This event with the movement of a mouse stroke replaced by `(dx, dy)`, e.g. to pass on the
+    /// part of a hardware move that is not remapped. Other strokes are returned unchanged.
+    pub fn with_mouse_move(self, dx: i32, dy: i32) -> Self {
+        match self.0 {
+            Stroke::Mouse {
+                state,
+                flags,
+                rolling,
+                information,
+                ..
+            } => Self(Stroke::Mouse {
+                state,
+                flags,
+                rolling,
+                x: dx,
+                y: dy,
+                information,
+            }),
+            stroke => Self(stroke),
+        }
+    }
 }
 
-impl KbdOut {
-    pub fn new() -> Result<Self, io::Error> {
-        Ok(Self {})
+/// Error decoding an [`InputEvent`] from bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputEventParseError {
+    /// The input is not the length of the stroke kind it starts with.
+    Length(usize),
+    /// The first byte is not a known stroke kind.
+    Kind(u8),
+    ScanCode(u16),
+    /// A state or flags field has bits that are not defined.
+    Flags(u16),
+}
+
+impl std::fmt::Display for InputEventParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Length(len) => write!(f, "invalid encoded input event length {len}"),
+            Self::Kind(kind) => write!(f, "unknown encoded input event kind {kind}"),
+            Self::ScanCode(code) => write!(f, "unknown scan code {code:#x}"),
+            Self::Flags(bits) => write!(f, "undefined state or flag bits in {bits:#x}"),
+        }
     }
+}
 
-    pub fn write(&mut self, event: InputEvent) -> Result<(), io::Error> {
-        write_interception(event);
-        Ok(())
+impl std::error::Error for InputEventParseError {}
+
+const ENCODED_KEYBOARD: u8 = 0;
+const ENCODED_MOUSE: u8 = 1;
+
+#[allow(dead_code)] // Nothing records or replays output from a file yet.
+impl InputEvent {
+    /// Encode the stroke compactly, for recording to a file or sending over IPC. The encoding is
+    /// a kind byte followed by the stroke's fields in little-endian order.
+    pub fn to_bytes(self) -> Vec<u8> {
+        match self.0 {
+            Stroke::Keyboard {
+                code,
+                state,
+                information,
+            } => {
+                let mut bytes = vec![ENCODED_KEYBOARD];
+                bytes.extend((code as u16).to_le_bytes());
+                bytes.extend(state.bits().to_le_bytes());
+                bytes.extend(information.to_le_bytes());
+                bytes
+            }
+            Stroke::Mouse {
+                state,
+                flags,
+                rolling,
+                x,
+                y,
+                information,
+            } => {
+                let mut bytes = vec![ENCODED_MOUSE];
+                bytes.extend(state.bits().to_le_bytes());
+                bytes.extend(flags.bits().to_le_bytes());
+                bytes.extend(rolling.to_le_bytes());
+                bytes.extend(x.to_le_bytes());
+                bytes.extend(y.to_le_bytes());
+                bytes.extend(information.to_le_bytes());
+                bytes
+            }
+        }
     }
 
-    pub fn write_code(&mut self, code: u32, value: KeyValue) -> Result<(), io::Error> {
-        super::write_code(code as u16, value)
+    /// Decode an event encoded by [`InputEvent::to_bytes`].
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, InputEventParseError> {
+        let u16_at = |i: usize| u16::from_le_bytes([bytes[i], bytes[i + 1]]);
+        let u32_at =
+            |i: usize| u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+        let expected_len = match bytes.first() {
+            Some(&ENCODED_KEYBOARD) => 9,
+            Some(&ENCODED_MOUSE) => 19,
+            Some(&kind) => return Err(InputEventParseError::Kind(kind)),
+            None => return Err(InputEventParseError::Length(0)),
+        };
+        if bytes.len() != expected_len {
+            return Err(InputEventParseError::Length(bytes.len()));
+        }
+        let stroke = if bytes[0] == ENCODED_KEYBOARD {
+            let code = u16_at(1);
+            let state = u16_at(3);
+            Stroke::Keyboard {
+                code: ScanCode::try_from(code).map_err(|_| InputEventParseError::ScanCode(code))?,
+                state: KeyState::from_bits(state).ok_or(InputEventParseError::Flags(state))?,
+                information: u32_at(5),
+            }
+        } else {
+            let state = u16_at(1);
+            let flags = u16_at(3);
+            Stroke::Mouse {
+                state: MouseState::from_bits(state).ok_or(InputEventParseError::Flags(state))?,
+                flags: MouseFlags::from_bits(flags).ok_or(InputEventParseError::Flags(flags))?,
+                rolling: u16_at(5) as i16,
+                x: u32_at(7) as i32,
+                y: u32_at(11) as i32,
+                information: u32_at(15),
+            }
+        };
+        Ok(Self(stroke))
     }
+}
+
+/// Relative mouse movement read from an intercepted mouse along one axis.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MouseMove {
+    pub direction: MoveDirection,
+    pub distance: u32,
+}
 
-    pub fn write_key(&mut self, key: OsCode, value: KeyValue) -> Result<(), io::Error> {
-        self.write(InputEvent::from_oscode(key, value))
+impl MouseMove {
+    /// Splits a movement of `(dx, dy)` into its horizontal and vertical parts, in that order, so
+    /// that each axis can be remapped on its own. An axis that did not move is `None`.
+    pub fn from_axes(dx: i32, dy: i32) -> [Option<Self>; 2] {
+        let axis = |delta: i32, negative, positive| {
+            (delta != 0).then(|| Self {
+                direction: if delta < 0 { negative } else { positive },
+                distance: delta.unsigned_abs(),
+            })
+        };
+        [
+            axis(dx, MoveDirection::Left, MoveDirection::Right),
+            axis(dy, MoveDirection::Up, MoveDirection::Down),
+        ]
     }
 
-    pub fn press_key(&mut self, key: OsCode) -> Result<(), io::Error> {
-        self.write_key(key, KeyValue::Press)
+    /// The key code that the movement is delivered to the processing loop as.
+    pub fn oscode(&self) -> OsCode {
+        match self.direction {
+            MoveDirection::Up => OsCode::MouseMoveUp,
+            MoveDirection::Down => OsCode::MouseMoveDown,
+            MoveDirection::Left => OsCode::MouseMoveLeft,
+            MoveDirection::Right => OsCode::MouseMoveRight,
+        }
     }
+}
 
-    pub fn release_key(&mut self, key: OsCode) -> Result<(), io::Error> {
-        self.write_key(key, KeyValue::Release)
+impl PartialEq for InputEvent {
+    fn eq(&self, other: &Self) -> bool {
+        match (self.0, other.0) {
+            (
+                Stroke::Keyboard {
+                    code: c1,
+                    state: s1,
+                    information: i1,
+                },
+                Stroke::Keyboard {
+                    code: c2,
+                    state: s2,
+                    information: i2,
+                },
+            ) => c1 == c2 && s1 == s2 && i1 == i2,
+            (
+                Stroke::Mouse {
+                    state: s1,
+                    flags: f1,
+                    rolling: r1,
+                    x: x1,
+                    y: y1,
+                    information: i1,
+                },
+                Stroke::Mouse {
+                    state: s2,
+                    flags: f2,
+                    rolling: r2,
+                    x: x2,
+                    y: y2,
+                    information: i2,
+                },
+            ) => s1 == s2 && f1 == f2 && r1 == r2 && x1 == x2 && y1 == y2 && i1 == i2,
+            _ => false,
+        }
     }
+}
 
-    pub fn click_btn(&mut self, btn: Btn) -> Result<(), io::Error> {
-        log::debug!("click btn: {:?}", btn);
-        write_interception(InputEvent::from_mouse_btn(btn, false));
-        Ok(())
+impl Eq for InputEvent {}
+
+impl std::fmt::Display for InputEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        const BUTTONS: [(MouseState, &str); 10] = [
+            (MouseState::LEFT_BUTTON_DOWN, "Left Down"),
+            (MouseState::LEFT_BUTTON_UP, "Left Up"),
+            (MouseState::RIGHT_BUTTON_DOWN, "Right Down"),
+            (MouseState::RIGHT_BUTTON_UP, "Right Up"),
+            (MouseState::MIDDLE_BUTTON_DOWN, "Middle Down"),
+            (MouseState::MIDDLE_BUTTON_UP, "Middle Up"),
+            (MouseState::BUTTON_4_DOWN, "Backward Down"),
+            (MouseState::BUTTON_4_UP, "Backward Up"),
+            (MouseState::BUTTON_5_DOWN, "Forward Down"),
+            (MouseState::BUTTON_5_UP, "Forward Up"),
+        ];
+        match self.0 {
+            Stroke::Keyboard { code, state, .. } => {
+                let extended = if state.contains(KeyState::E0) {
+                    "E0 "
+                } else {
+                    ""
+                };
+                let value = if state.contains(KeyState::UP) {
+                    "Release"
+                } else if is_repeat(&self.0) {
+                    "Repeat"
+                } else {
+                    "Press"
+                };
+                write!(f, "Key({extended}{code:?}, {value})")
+            }
+            Stroke::Mouse {
+                state,
+                flags,
+                rolling,
+                x,
+                y,
+                ..
+            } => {
+                if let Some((_, name)) = BUTTONS.iter().find(|(btn, _)| state.contains(*btn)) {
+                    write!(f, "Mouse({name})")
+                } else if state.contains(MouseState::WHEEL) {
+                    let direction = if rolling < 0 { "Down" } else { "Up" };
+                    write!(f, "Scroll({direction} {})", rolling.unsigned_abs())
+                } else if state.contains(MouseState::HWHEEL) {
+                    let direction = if rolling < 0 { "Left" } else { "Right" };
+                    write!(f, "Scroll({direction} {})", rolling.unsigned_abs())
+                } else if flags.contains(MouseFlags::MOVE_ABSOLUTE) {
+                    write!(f, "MoveTo({x},{y})")
+                } else {
+                    write!(f, "Move({x},{y})")
+                }
+            }
+        }
     }
+}
 
-    pub fn release_btn(&mut self, btn: Btn) -> Result<(), io::Error> {
-        log::debug!("release btn: {:?}", btn);
-        let event = InputEvent::from_mouse_btn(btn, true);
-        write_interception(event);
-        Ok(())
+/// Returns the UTF-16 code units of `c`, each paired with its up state, in the order they should
+/// be sent.
+fn unicode_packets(c: char) -> impl Iterator<Item = (u16, bool)> {
+    let mut buf = [0u16; 2];
+    let len = c.encode_utf16(&mut buf).len();
+    buf.into_iter()
+        .take(len)
+        .flat_map(|unit| [(unit, false), (unit, true)])
+}
+
+/// Same as [`unicode_packets`] but for every character of `s`.
+fn unicode_string_packets(s: &str) -> impl Iterator<Item = (u16, bool)> + '_ {
+    s.encode_utf16()
+        .flat_map(|unit| [(unit, false), (unit, true)])
+}
+
+/// Returns the key that types the ASCII character `c` on a US layout and whether shift must be held
+/// for it. Returns `None` for characters without a key, such as most control characters.
+fn ascii_to_key(c: char) -> Option<(OsCode, bool)> {
+    use OsCode::*;
+    let shifted = c.is_ascii_uppercase()
+        || matches!(
+            c,
+            '~' | '!'
+                | '@'
+                | '#'
+                | '$'
+                | '%'
+                | '^'
+                | '&'
+                | '*'
+                | '('
+                | ')'
+                | '_'
+                | '+'
+                | '{'
+                | '}'
+                | '|'
+                | ':'
+                | '"'
+                | '<'
+                | '>'
+                | '?'
+        );
+    let key = match c.to_ascii_lowercase() {
+        'a' => KEY_A,
+        'b' => KEY_B,
+        'c' => KEY_C,
+        'd' => KEY_D,
+        'e' => KEY_E,
+        'f' => KEY_F,
+        'g' => KEY_G,
+        'h' => KEY_H,
+        'i' => KEY_I,
+        'j' => KEY_J,
+        'k' => KEY_K,
+        'l' => KEY_L,
+        'm' => KEY_M,
+        'n' => KEY_N,
+        'o' => KEY_O,
+        'p' => KEY_P,
+        'q' => KEY_Q,
+        'r' => KEY_R,
+        's' => KEY_S,
+        't' => KEY_T,
+        'u' => KEY_U,
+        'v' => KEY_V,
+        'w' => KEY_W,
+        'x' => KEY_X,
+        'y' => KEY_Y,
+        'z' => KEY_Z,
+        '1' | '!' => KEY_1,
+        '2' | '@' => KEY_2,
+        '3' | '#' => KEY_3,
+        '4' | '$' => KEY_4,
+        '5' | '%' => KEY_5,
+        '6' | '^' => KEY_6,
+        '7' | '&' => KEY_7,
+        '8' | '*' => KEY_8,
+        '9' | '(' => KEY_9,
+        '0' | ')' => KEY_0,
+        '-' | '_' => KEY_MINUS,
+        '=' | '+' => KEY_EQUAL,
+        '[' | '{' => KEY_LEFTBRACE,
+        ']' | '}' => KEY_RIGHTBRACE,
+        '\\' | '|' => KEY_BACKSLASH,
+        ';' | ':' => KEY_SEMICOLON,
+        '\'' | '"' => KEY_APOSTROPHE,
+        '`' | '~' => KEY_GRAVE,
+        ',' | '<' => KEY_COMMA,
+        '.' | '>' => KEY_DOT,
+        '/' | '?' => KEY_SLASH,
+        ' ' => KEY_SPACE,
+        '\t' => KEY_TAB,
+        '\n' => KEY_ENTER,
+        _ => return None,
+    };
+    Some((key, shifted))
+}
+
+/// Position and size in pixels of the virtual screen, the bounding rectangle of all monitors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScreenMetrics {
+    pub left: i32,
+    pub top: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl ScreenMetrics {
+    fn query() -> Self {
+        let (left, top, width, height) = super::virtual_screen_rect();
+        Self {
+            left,
+            top,
+            width,
+            height,
+        }
     }
 
-    pub fn scroll(&mut self, direction: MWheelDirection, distance: u16) -> Result<(), io::Error> {
-        log::debug!("scroll: {direction:?} {distance:?}");
-        write_interception(InputEvent::from_mouse_scroll(direction, distance));
-        Ok(())
+    /// Clamps desktop coordinates to the screen and returns them as offsets from its top-left
+    /// corner.
+    fn clamp(&self, x: i32, y: i32) -> (u32, u32) {
+        let offset = |pos: i32, start: i32, len: u32| {
+            let max = i64::from(len.saturating_sub(1));
+            (i64::from(pos) - i64::from(start)).clamp(0, max) as u32
+        };
+        (
+            offset(x, self.left, self.width),
+            offset(y, self.top, self.height),
+        )
     }
+}
 
-    /// Send using VK_PACKET
-    pub fn send_unicode(&mut self, c: char) -> Result<(), io::Error> {
-        super::send_uc(c, false);
-        super::send_uc(c, true);
-        Ok(())
+/// Whether `key` is a mouse button, wheel or movement rather than a key.
+fn is_mouse_code(key: OsCode) -> bool {
+    matches!(
+        key,
+        OsCode::BTN_LEFT
+            | OsCode::BTN_RIGHT
+            | OsCode::BTN_MIDDLE
+            | OsCode::BTN_SIDE
+            | OsCode::BTN_EXTRA
+            | OsCode::BTN_FORWARD
+            | OsCode::BTN_BACK
+            | OsCode::BTN_TASK
+            | OsCode::MouseWheelUp
+            | OsCode::MouseWheelDown
+            | OsCode::MouseWheelLeft
+            | OsCode::MouseWheelRight
+            | OsCode::MouseMoveUp
+            | OsCode::MouseMoveDown
+            | OsCode::MouseMoveLeft
+            | OsCode::MouseMoveRight
+    )
+}
+
+/// Rectangle in desktop pixel coordinates that [`KbdOut::move_mouse_in_box`] confines the cursor
+/// to. The right and bottom edges are exclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub left: i32,
+    pub top: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    /// Clamps desktop coordinates into the rectangle.
+    fn clamp(&self, x: i32, y: i32) -> (i32, i32) {
+        let clamp = |pos: i32, start: i32, len: u32| {
+            let end = i64::from(start) + i64::from(len.saturating_sub(1));
+            i64::from(pos).clamp(i64::from(start), end) as i32
+        };
+        (
+            clamp(x, self.left, self.width),
+            clamp(y, self.top, self.height),
+        )
     }
 
-    pub fn move_mouse(&mut self, mv: CalculatedMouseMove) -> Result<(), io::Error> {
-        write_interception(InputEvent::from_mouse_move(mv.direction, mv.distance));
-        Ok(())
+    fn center(&self) -> (i32, i32) {
+        (
+            (i64::from(self.left) + i64::from(self.width / 2)) as i32,
+            (i64::from(self.top) + i64::from(self.height / 2)) as i32,
+        )
+    }
+}
+
+/// Converts a pixel coordinate on an axis that is `screen_size` pixels long to the 0-65535 range
+/// used by absolute mouse movement. Coordinates past the end of the axis are clamped.
+fn pixel_to_normalized(pixel: u32, screen_size: u32) -> u16 {
+    if screen_size <= 1 {
+        return 0;
+    }
+    let max_pixel = screen_size - 1;
+    let normalized = u64::from(pixel.min(max_pixel)) * u64::from(u16::MAX) / u64::from(max_pixel);
+    normalized as u16
+}
+
+/// Acceleration profile for a held mouse movement or scroll. The distance per tick ramps linearly
+/// from `start_distance` to `max_distance` over `ticks_to_max` ticks, like `movemouse-accel` does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccelRamp {
+    pub start_distance: u16,
+    pub max_distance: u16,
+    pub ticks_to_max: u32,
+}
+
+impl AccelRamp {
+    /// No acceleration: `distance` on every tick.
+    pub const fn constant(distance: u16) -> Self {
+        Self {
+            start_distance: distance,
+            max_distance: distance,
+            ticks_to_max: 0,
+        }
+    }
+
+    /// Distance on the `tick`th tick of the movement, starting from 0.
+    pub fn distance_at(&self, tick: u32) -> u16 {
+        if tick >= self.ticks_to_max {
+            return self.max_distance;
+        }
+        let start = i64::from(self.start_distance);
+        let max = i64::from(self.max_distance);
+        let distance = start + (max - start) * i64::from(tick) / i64::from(self.ticks_to_max);
+        distance as u16
+    }
+}
+
+/// Cursor movement that continues after a flick of a move key, slowing by a constant factor on
+/// every tick until it stops.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Inertia {
+    direction: MoveDirection,
+    velocity: f32,
+}
+
+/// Time to wait after pasting before restoring the clipboard. The application reads the clipboard
+/// when it handles ctrl+v, which can be after the keys have been sent.
+const CLIPBOARD_RESTORE_DELAY_MS: u32 = 100;
+
+/// Default factor that the inertia velocity is multiplied by on every tick.
+pub const DEFAULT_INERTIA_DECAY: f32 = 0.9;
+
+thread_local! {
+    static INTRCPTN: Interception = Interception::new().expect("interception driver should init: have you completed the interception driver installation?");
+}
+
+/// A single unit of output from [`KbdOut`]. Most output goes to the interception driver as a
+/// stroke, but unicode and raw virtual key codes are sent with `SendInput`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutEvent {
+    Stroke(InputEvent),
+    /// A VK_PACKET carrying one UTF-16 code unit.
+    Unicode {
+        unit: u16,
+        up: bool,
+    },
+    /// A virtual key code sent by [`KbdOut::write_code`].
+    VirtualKey {
+        code: u16,
+        value: KeyValue,
+    },
+}
+
+/// Output that [`KbdOut::tick`] sends once it is due.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ScheduledAction {
+    ReleaseKey(OsCode),
+    ReleaseBtn(Btn),
+    Click(Btn),
+    RestoreClipboard,
+    Write(InputEvent),
+    /// Auto-repeat of a key held with [`KbdOut::hold_repeat`], which schedules itself again.
+    Repeat {
+        key: OsCode,
+        interval_ms: u32,
+    },
+    /// A character of the unicode output methods that waits for the character delay.
+    Unicode(char),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Scheduled {
+    /// Value of the `KbdOut` clock at which the action is due.
+    due_ms: u64,
+    action: ScheduledAction,
+}
+
+/// A set of modifier keys, one bit per key, for [`KbdOut::apply_modifiers`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ModBits(pub u8);
+
+impl ModBits {
+    pub const NONE: Self = Self(0);
+    pub const LCTRL: Self = Self(1 << 0);
+    pub const LSHIFT: Self = Self(1 << 1);
+    pub const LALT: Self = Self(1 << 2);
+    pub const LMETA: Self = Self(1 << 3);
+    pub const RCTRL: Self = Self(1 << 4);
+    pub const RSHIFT: Self = Self(1 << 5);
+    pub const RALT: Self = Self(1 << 6);
+    pub const RMETA: Self = Self(1 << 7);
+
+    const KEYS: [(Self, OsCode); 8] = [
+        (Self::LCTRL, OsCode::KEY_LEFTCTRL),
+        (Self::LSHIFT, OsCode::KEY_LEFTSHIFT),
+        (Self::LALT, OsCode::KEY_LEFTALT),
+        (Self::LMETA, OsCode::KEY_LEFTMETA),
+        (Self::RCTRL, OsCode::KEY_RIGHTCTRL),
+        (Self::RSHIFT, OsCode::KEY_RIGHTSHIFT),
+        (Self::RALT, OsCode::KEY_RIGHTALT),
+        (Self::RMETA, OsCode::KEY_RIGHTMETA),
+    ];
+
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// The modifier keys in the set.
+    pub fn keys(self) -> impl Iterator<Item = OsCode> {
+        Self::KEYS
+            .into_iter()
+            .filter(move |(bit, _)| self.contains(*bit))
+            .map(|(_, key)| key)
+    }
+}
+
+impl std::ops::BitOr for ModBits {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        self.union(rhs)
+    }
+}
+
+/// Counts of the events sent by a [`KbdOut`], for diagnosing dropped or runaway output.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct KbdOutStats {
+    /// Key down events, including repeats and unicode packets.
+    pub key_presses: u64,
+    /// Key up events, including unicode packets.
+    pub key_releases: u64,
+    /// Mouse button presses.
+    pub clicks: u64,
+    /// Wheel events on either axis.
+    pub scroll_ticks: u64,
+    /// Relative or absolute mouse movement events.
+    pub mouse_moves: u64,
+}
+
+impl KbdOutStats {
+    fn record(&mut self, event: &OutEvent) {
+        const BUTTONS_DOWN: MouseState = MouseState::LEFT_BUTTON_DOWN
+            .union(MouseState::RIGHT_BUTTON_DOWN)
+            .union(MouseState::MIDDLE_BUTTON_DOWN)
+            .union(MouseState::BUTTON_4_DOWN)
+            .union(MouseState::BUTTON_5_DOWN);
+        let counter = match *event {
+            OutEvent::Stroke(InputEvent(Stroke::Keyboard { state, .. })) => {
+                if state.contains(KeyState::UP) {
+                    &mut self.key_releases
+                } else {
+                    &mut self.key_presses
+                }
+            }
+            OutEvent::Stroke(InputEvent(Stroke::Mouse { state, .. })) => {
+                if state.intersects(BUTTONS_DOWN) {
+                    &mut self.clicks
+                } else if state.intersects(MouseState::WHEEL | MouseState::HWHEEL) {
+                    &mut self.scroll_ticks
+                } else if state.intersects(MouseState::all().difference(MouseState::MOVE)) {
+                    // Button releases are not counted.
+                    return;
+                } else {
+                    &mut self.mouse_moves
+                }
+            }
+            OutEvent::Unicode { up: true, .. }
+            | OutEvent::VirtualKey {
+                value: KeyValue::Release,
+                ..
+            } => &mut self.key_releases,
+            OutEvent::Unicode { up: false, .. } | OutEvent::VirtualKey { .. } => {
+                &mut self.key_presses
+            }
+        };
+        *counter += 1;
+    }
+}
+
+/// Callback that sees every stroke before it is sent, and drops it by returning `false`.
+pub type PreSendHook = Box<dyn FnMut(&InputEvent) -> bool + Send>;
+
+/// How the unicode output methods, such as [`KbdOut::send_unicode`], type characters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnicodeMethod {
+    /// `SendInput` with `KEYEVENTF_UNICODE`, which applications receive as `VK_PACKET` key
+    /// events that carry the character. This types any character on any keyboard layout, and
+    /// is the default.
+    VkPacket,
+    /// The virtual key that types the character on the current keyboard layout, with shift,
+    /// ctrl and alt as needed, for applications that ignore `VK_PACKET`. Characters that the
+    /// layout has no key for are sent as `VkPacket`.
+    KeyScan,
+}
+
+/// Handle for writing keys to the OS.
+pub struct KbdOut {
+    /// Delay between the characters sent by the unicode output methods. The delay applies per
+    /// character, not per UTF-16 code unit, so a surrogate pair is never split by it.
+    unicode_char_delay_ms: Option<u32>,
+    /// Clock time at which the last character was typed by the unicode output methods.
+    last_unicode_ms: Option<u64>,
+    /// See [`KbdOut::set_unicode_method`].
+    unicode_method: UnicodeMethod,
+    /// Time between the press and release of a tap, see [`KbdOut::set_tap_gap`].
+    tap_gap_ms: u32,
+    hires_scroll: ScrollAccumulator,
+    /// Whether [`KbdOut::scroll`] only sends whole notches, see
+    /// [`KbdOut::set_scroll_accumulation`].
+    accumulate_scroll: bool,
+    /// Distance given to [`KbdOut::scroll`] that does not make up a whole notch yet.
+    scroll_remainder: ScrollAccumulator,
+    /// Relative mouse movement that has not been sent yet. Consecutive moves are combined into a
+    /// single stroke that is sent by [`KbdOut::flush`], or before the next non-move event. For a
+    /// held diagonal mouse movement this halves the number of strokes: the vertical and
+    /// horizontal moves of each engine tick go out as one stroke instead of two.
+    pending_move: (i32, i32),
+    /// Granularity of sent relative movement, see [`KbdOut::set_move_step`].
+    move_step: u16,
+    /// Movement smaller than a move step that has not been sent yet.
+    move_step_remainder: (i32, i32),
+    /// Multiplier for relative mouse movement distances.
+    dpi_scale: f32,
+    /// Fractional pixels of scaled movement not sent yet, per axis.
+    dpi_remainder: (f32, f32),
+    /// Fractional pixels of [`KbdOut::move_mouse_polar`] movement not sent yet, per axis.
+    polar_remainder: (f32, f32),
+    /// Minimum interval between flushed mouse movement strokes.
+    mouse_rate_limit: Option<Duration>,
+    last_move_sent: Option<Instant>,
+    /// Reverse the scroll direction on both axes, i.e. natural scrolling.
+    invert_scroll: bool,
+    /// Keys that have been pressed and not yet released.
+    held_keys: HashSet<OsCode>,
+    /// Do not send releases for keys that are not in `held_keys`.
+    skip_unheld_releases: bool,
+    /// Raw virtual key codes that have been pressed with `write_code` and not yet released.
+    held_codes: HashSet<u32>,
+    /// Whether the forward and backward buttons are swapped, see [`KbdOut::set_swap_nav_buttons`].
+    swap_nav_buttons: bool,
+    /// Mouse buttons that have been pressed and not yet released.
+    held_btns: HashSet<Btn>,
+    /// Do not send presses for buttons that are already in `held_btns`.
+    skip_held_clicks: bool,
+    /// Release buttons that have been held this long without a refresh, see
+    /// [`KbdOut::set_button_watchdog`].
+    button_watchdog: Option<Duration>,
+    /// The value of `clock_ms` when each held button was last pressed or refreshed.
+    btn_refreshed: HashMap<Btn, u64>,
+    mouse_accel: AccelRamp,
+    scroll_accel: AccelRamp,
+    /// Interception device to send strokes to. Keyboard devices are 1-10 and mouse devices are
+    /// 11-20; the device is only used for strokes of its own kind, and the first device of the
+    /// matching range is used otherwise, as it is when this is `None`.
+    device: Option<Device>,
+    /// Maximum distance of a single vertical scroll.
+    max_scroll_vertical: u16,
+    /// Maximum distance of a single horizontal scroll.
+    max_scroll_horizontal: u16,
+    stats: KbdOutStats,
+    /// Milliseconds elapsed, as counted by [`KbdOut::tick`].
+    clock_ms: u64,
+    /// Output waiting for a later tick, in the order it was scheduled.
+    scheduled: Vec<Scheduled>,
+    /// Movement that is coasting to a stop, advanced by [`KbdOut::tick_inertia`].
+    inertia: Option<Inertia>,
+    /// Factor in `[0, 1)` that the inertia velocity is multiplied by on every tick.
+    inertia_decay: f32,
+    /// Cached virtual screen metrics for absolute moves, queried on first use.
+    screen: Option<ScreenMetrics>,
+    /// Desktop coordinates of the last [`KbdOut::move_mouse_to`], after clamping.
+    last_absolute: Option<(i32, i32)>,
+    /// Cursor position tracked from the relative moves sent, see [`KbdOut::virtual_position`].
+    /// `None` until it is seeded.
+    virtual_position: Option<(i32, i32)>,
+    /// Cursor position tracked by [`KbdOut::move_mouse_in_box`].
+    box_position: Option<(i32, i32)>,
+    /// Whether [`KbdOut::paste_text`] restores the previous clipboard contents afterwards.
+    paste_preserves_clipboard: bool,
+    /// Clipboard contents from before the last paste, waiting to be restored.
+    saved_clipboard: Option<String>,
+    /// Clipboard used in place of the OS clipboard by a simulated handle.
+    simulated_clipboard: Option<String>,
+    /// Written into the `information` field of every generated stroke.
+    source_id: u16,
+    /// Called with every stroke before it is sent; see [`KbdOut::set_pre_send_hook`].
+    pre_send_hook: Option<PreSendHook>,
+    /// When set, all output is dropped. See [`KbdOut::set_suspended`].
+    suspended: bool,
+    /// When set, output is captured here instead of being sent to the OS.
+    simulated: Option<Vec<OutEvent>>,
+    /// The first event that could not be written since this was last taken, reported by
+    /// [`KbdOutSender::poll_errors`].
+    failed_event: Option<OutEvent>,
+    /// Also receives the simulated output, see [`KbdOut::new_with_channel`].
+    #[cfg(test)]
+    test_channel: Option<std::sync::mpsc::Sender<OutEvent>>,
+    /// Makes the simulated writes of the events it returns true for fail, as a write to the
+    /// driver can.
+    #[cfg(test)]
+    fail_simulated_writes: Option<fn(&OutEvent) -> bool>,
+}
+
+/// Default maximum distance of a single scroll, on either axis. This matches the bound checked on
+/// scroll actions in the configuration.
+pub const DEFAULT_MAX_SCROLL_DISTANCE: u16 = 30000;
+
+/// The largest distance that fits in the rolling value of a wheel stroke.
+const MAX_STROKE_SCROLL_DISTANCE: u16 = i16::MAX as u16;
+
+/// Checks a scroll `distance`, computed in a wide type so that the math before it cannot
+/// overflow, against the bound `max_distance`, which is clamped to what a wheel stroke can hold.
+/// Every scroll goes through this check.
+fn bounded_scroll_distance(
+    direction: MWheelDirection,
+    distance: u64,
+    max_distance: u16,
+) -> Result<u16, KbdOutError> {
+    let max_distance = max_distance.min(MAX_STROKE_SCROLL_DISTANCE);
+    match u16::try_from(distance) {
+        Ok(distance) if distance <= max_distance => Ok(distance),
+        _ => Err(KbdOutError::InvalidInput(format!(
+            "scroll distance {distance} for {direction:?} is above the maximum of {max_distance}"
+        ))),
+    }
+}
+
+/// Rounds a scaled distance to whole pixels, saturating at the range of an `i32`.
+fn saturating_pixels(distance: f32) -> i32 {
+    // Float to integer casts saturate, and map NaN to 0.
+    distance.round() as i32
+}
+
+/// Rolling distance of one wheel notch, as Win32 defines it. This is the signed form of
+/// `HI_RES_SCROLL_UNITS_IN_LO_RES`, for notch math on wheel stroke rolling values.
+pub const WHEEL_DELTA: i16 = HI_RES_SCROLL_UNITS_IN_LO_RES as i16;
+
+/// Number of wheel notches that [`KbdOut::scroll_page`] scrolls per page. Windows has no wheel
+/// event for a page, so a page is sent as this many notches, which is 30 lines with the default
+/// setting of 3 lines per notch.
+pub const PAGE_SCROLL_NOTCHES: u16 = 10;
+
+/// Accumulates scrolling in hundredths of a wheel notch, per axis, so that sub-notch amounts are
+/// carried forward instead of being lost.
+#[derive(Debug, Default, Clone, Copy)]
+struct ScrollAccumulator {
+    /// Positive is up.
+    vertical: i64,
+    /// Positive is right.
+    horizontal: i64,
+}
+
+impl ScrollAccumulator {
+    /// Adds `hundredths` of a notch in `direction`. Returns the number of whole notches that are
+    /// now ready to be sent for that axis; positive means up or right. The count is removed from
+    /// the accumulator and any remainder is kept.
+    fn add(&mut self, direction: MWheelDirection, hundredths: u32) -> i64 {
+        self.add_scaled(direction, hundredths, 100)
+    }
+
+    /// Like [`ScrollAccumulator::add`], for an `amount` in units of which `per_notch` make up a
+    /// notch. An accumulator should always be used with the same unit.
+    fn add_scaled(&mut self, direction: MWheelDirection, amount: u32, per_notch: i64) -> i64 {
+        let (acc, amount) = match direction {
+            MWheelDirection::Up => (&mut self.vertical, i64::from(amount)),
+            MWheelDirection::Down => (&mut self.vertical, -i64::from(amount)),
+            MWheelDirection::Right => (&mut self.horizontal, i64::from(amount)),
+            MWheelDirection::Left => (&mut self.horizontal, -i64::from(amount)),
+        };
+        *acc = acc.saturating_add(amount);
+        let notches = *acc / per_notch;
+        *acc -= notches * per_notch;
+        notches
+    }
+}
+
+// Note regarding device numbers:
+// Keyboard devices are 1-10 and mouse devices are 11-20. Source:
+// https://github.com/oblitum/Interception/blob/39eecbbc46a52e0402f783b872ef62b0254a896a/library/interception.h#L34
+const KEYBOARD_DEVICES: std::ops::RangeInclusive<Device> = 1..=10;
+const MOUSE_DEVICES: std::ops::RangeInclusive<Device> = 11..=20;
+
+/// Returns the device that `stroke` should be sent to. `device` is used if it is in the range
+/// matching the kind of stroke, otherwise the first device of that range is.
+fn target_device(stroke: &Stroke, device: Option<Device>) -> Device {
+    let devices = match stroke {
+        Stroke::Keyboard { .. } => KEYBOARD_DEVICES,
+        Stroke::Mouse { .. } => MOUSE_DEVICES,
+    };
+    device
+        .filter(|d| devices.contains(d))
+        .unwrap_or(*devices.start())
+}
+
+fn write_interception(event: InputEvent, device: Option<Device>) -> Result<(), io::Error> {
+    log::debug!("kanata sending {event} to driver");
+    let strokes = [event.0];
+    let device = target_device(&strokes[0], device);
+    let sent = INTRCPTN.with(|ic| ic.send(device, &strokes[0..1]));
+    if sent < 1 {
+        return Err(io::Error::new(
+            io::ErrorKind::BrokenPipe,
+            format!("interception driver did not accept {:?}", strokes[0]),
+        ));
+    }
+    Ok(())
+}
+
+impl KbdOut {
+    /// Create a handle with every setting at its default. Use [`KbdOutBuilder`] to create one
+    /// with other settings.
+    pub fn new() -> Result<Self, KbdOutError> {
+        KbdOutBuilder::new().build()
+    }
+
+    fn with_defaults() -> Self {
+        Self {
+            unicode_char_delay_ms: None,
+            last_unicode_ms: None,
+            unicode_method: UnicodeMethod::VkPacket,
+            tap_gap_ms: 0,
+            hires_scroll: ScrollAccumulator::default(),
+            accumulate_scroll: false,
+            scroll_remainder: ScrollAccumulator::default(),
+            pending_move: (0, 0),
+            move_step: 1,
+            move_step_remainder: (0, 0),
+            dpi_scale: 1.0,
+            dpi_remainder: (0.0, 0.0),
+            polar_remainder: (0.0, 0.0),
+            mouse_rate_limit: None,
+            last_move_sent: None,
+            invert_scroll: false,
+            held_keys: HashSet::default(),
+            skip_unheld_releases: false,
+            held_codes: HashSet::default(),
+            swap_nav_buttons: false,
+            held_btns: HashSet::default(),
+            skip_held_clicks: true,
+            button_watchdog: None,
+            btn_refreshed: HashMap::default(),
+            mouse_accel: AccelRamp::constant(1),
+            scroll_accel: AccelRamp::constant(HI_RES_SCROLL_UNITS_IN_LO_RES),
+            device: None,
+            max_scroll_vertical: DEFAULT_MAX_SCROLL_DISTANCE,
+            max_scroll_horizontal: DEFAULT_MAX_SCROLL_DISTANCE,
+            stats: KbdOutStats::default(),
+            clock_ms: 0,
+            scheduled: Vec::new(),
+            inertia: None,
+            inertia_decay: DEFAULT_INERTIA_DECAY,
+            screen: None,
+            last_absolute: None,
+            virtual_position: None,
+            box_position: None,
+            paste_preserves_clipboard: true,
+            saved_clipboard: None,
+            simulated_clipboard: None,
+            source_id: 0,
+            pre_send_hook: None,
+            suspended: false,
+            simulated: None,
+            failed_event: None,
+            #[cfg(test)]
+            test_channel: None,
+            #[cfg(test)]
+            fail_simulated_writes: None,
+        }
+    }
+
+    /// Create a handle that sends strokes to the interception `device`. Keyboard strokes only use
+    /// it if it is a keyboard device and mouse strokes only if it is a mouse device.
+    #[allow(dead_code)] // The processing loop sends to the default devices.
+    pub fn with_device(device: Device) -> Result<Self, KbdOutError> {
+        KbdOutBuilder::new().device(device).build()
+    }
+
+    /// Create a handle that records all output instead of sending it, so that the exact output
+    /// sequence can be checked without the driver installed. Use [`KbdOut::drain_simulated`] to
+    /// retrieve what was recorded.
+    #[cfg(test)]
+    pub fn new_simulated() -> Self {
+        KbdOutBuilder::new()
+            .simulated()
+            .build()
+            .expect("creating output does not fail")
+    }
+
+    /// Create a simulated handle that also sends everything it records to `tx`, so that a test
+    /// can observe the output of a handle it does not own, e.g. one moved into the processing
+    /// loop or a [`KbdOutSender`].
+    #[cfg(test)]
+    pub fn new_with_channel(tx: std::sync::mpsc::Sender<OutEvent>) -> Self {
+        Self {
+            test_channel: Some(tx),
+            ..Self::new_simulated()
+        }
     }
 
-    pub fn move_mouse_many(&mut self, moves: &[CalculatedMouseMove]) -> Result<(), io::Error> {
-        write_interception(InputEvent::from_mouse_move_many(moves));
+    /// Returns and clears the output recorded since the last call. Pending mouse movement is not
+    /// included until it has been flushed. Always empty if this handle is not simulated.
+    #[cfg(test)]
+    pub fn drain_simulated(&mut self) -> Vec<OutEvent> {
+        self.simulated
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    /// Every event leaves through here, whether it goes to the OS or to the simulation record.
+    fn send(&mut self, event: OutEvent) -> Result<(), KbdOutError> {
+        if self.suspended {
+            log::trace!("suspended, dropping {event:?}");
+            return Ok(());
+        }
+        let mut event = event;
+        if let OutEvent::Stroke(InputEvent(stroke)) = &mut event {
+            // Raw strokes that kanata did not generate are passed through untouched.
+            if is_synthetic(stroke) {
+                match stroke {
+                    Stroke::Keyboard { information, .. } | Stroke::Mouse { information, .. } => {
+                        *information =
+                            *information & !KANATA_SOURCE_ID_MASK | u32::from(self.source_id);
+                    }
+                }
+            }
+        }
+        if let (OutEvent::Stroke(stroke), Some(hook)) = (&event, &mut self.pre_send_hook) {
+            if !hook(stroke) {
+                log::trace!("pre-send hook dropped {stroke}");
+                return Ok(());
+            }
+        }
+        let relative_move = match event {
+            OutEvent::Stroke(InputEvent(Stroke::Mouse { flags, x, y, .. }))
+                if !flags.contains(MouseFlags::MOVE_ABSOLUTE) && (x, y) != (0, 0) =>
+            {
+                // Seed the tracked position before the move reaches the OS.
+                self.virtual_position();
+                Some((x, y))
+            }
+            _ => None,
+        };
+        if let Some(simulated) = &mut self.simulated {
+            #[cfg(test)]
+            if self
+                .fail_simulated_writes
+                .is_some_and(|fails| fails(&event))
+            {
+                self.failed_event.get_or_insert(event);
+                return Err(io::Error::new(io::ErrorKind::BrokenPipe, "simulated failure").into());
+            }
+            simulated.push(event);
+            #[cfg(test)]
+            if let Some(tx) = &self.test_channel {
+                // The receiver may be gone; the output is still recorded.
+                let _ = tx.send(event);
+            }
+        } else {
+            let res = match event {
+                OutEvent::Stroke(event) => write_interception(event, self.device),
+                OutEvent::Unicode { unit, up } => super::send_uc_unit(unit, up),
+                OutEvent::VirtualKey { code, value } => super::write_code(code, value),
+            };
+            if let Err(e) = res {
+                self.failed_event.get_or_insert(event);
+                return Err(e.into());
+            }
+        }
+        if let Some((dx, dy)) = relative_move {
+            self.track_relative_move(dx, dy);
+        }
+        self.stats.record(&event);
         Ok(())
     }
 
-    pub fn set_mouse(&mut self, x: u16, y: u16) -> Result<(), io::Error> {
-        write_interception(InputEvent::from_mouse_set(x, y));
+    /// Suspend or resume all output. Everything held is released before suspending, so that no
+    /// key is stuck down while output is dropped, and scheduled output and mouse movement in
+    /// progress are discarded. While suspended, every write succeeds without sending anything.
+    #[allow(dead_code)] // No action suspends output yet.
+    pub fn set_suspended(&mut self, suspended: bool) -> Result<(), KbdOutError> {
+        if suspended == self.suspended {
+            return Ok(());
+        }
+        if suspended {
+            self.release_all_held()?;
+            self.scheduled.clear();
+            self.inertia = None;
+            self.pending_move = (0, 0);
+        } else {
+            // Presses made while suspended were never sent.
+            self.held_keys.clear();
+            self.held_codes.clear();
+            self.held_btns.clear();
+        }
+        self.suspended = suspended;
         Ok(())
     }
-}
+
+    /// Kill switch for a misbehaving config: release every held key, raw key code and mouse
+    /// button, drop all scheduled output and mouse movement, and suspend output. A release that
+    /// fails does not stop the others, and output is suspended even then; the first error is
+    /// returned. Resume with [`KbdOut::set_suspended`].
+    #[allow(dead_code)] // For a kill switch, which the configuration does not have yet.
+    pub fn panic_release(&mut self) -> Result<(), KbdOutError> {
+        log::warn!("panic release: releasing everything and suspending output");
+        self.cancel_scheduled();
+        self.pending_move = (0, 0);
+        let mut first_err = None;
+        let mut record = |res: Result<(), KbdOutError>| {
+            if let Err(e) = res {
+                log::error!("panic release: {e}");
+                first_err.get_or_insert(e);
+            }
+        };
+        for key in std::mem::take(&mut self.held_keys) {
+            record(
+                InputEvent::from_oscode(key, KeyValue::Release)
+                    .map_err(KbdOutError::from)
+                    .and_then(|event| self.write(event)),
+            );
+        }
+        for code in std::mem::take(&mut self.held_codes) {
+            record(self.send(OutEvent::VirtualKey {
+                code: code as u16,
+                value: KeyValue::Release,
+            }));
+        }
+        for btn in std::mem::take(&mut self.held_btns) {
+            record(
+                InputEvent::from_mouse_btn(self.output_btn(btn), true)
+                    .and_then(|event| self.write(event)),
+            );
+        }
+        self.btn_refreshed.clear();
+        self.suspended = true;
+        match first_err {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    /// Set the id written into the `information` field of every stroke that kanata generates,
+    /// next to [`KANATA_SYNTHETIC_MARK`], so that tools further down the input stack can tell
+    /// which instance an event came from. It can be read back with [`source_id`]. The default
+    /// is 0. The id is 16 bits wide, see [`KANATA_SOURCE_ID_MASK`].
+    pub fn set_source_id(&mut self, id: u16) {
+        self.source_id = id;
+    }
+
+    /// Set a callback that is called with every stroke, keyboard or mouse, before it is sent.
+    /// Returning `false` drops the stroke, which the caller still sees as sent. Unicode and
+    /// virtual key output is not a stroke and does not go through the hook. `None` removes it.
+    #[allow(dead_code)] // Only tests install a hook so far.
+    pub fn set_pre_send_hook(&mut self, hook: Option<PreSendHook>) {
+        self.pre_send_hook = hook;
+    }
+}
+
+// Nothing reports these yet; the tests read them back.
+#[allow(dead_code)]
+impl KbdOut {
+    /// Counts of the events sent so far.
+    pub fn stats(&self) -> KbdOutStats {
+        self.stats
+    }
+
+    // The current settings, for diagnostics such as debug logs and status reports. Each one is
+    // changed by the matching setter or builder option.
+
+    pub fn device(&self) -> Option<Device> {
+        self.device
+    }
+
+    pub fn is_suspended(&self) -> bool {
+        self.suspended
+    }
+
+    pub fn source_id(&self) -> u16 {
+        self.source_id
+    }
+
+    pub fn is_scroll_inverted(&self) -> bool {
+        self.invert_scroll
+    }
+
+    pub fn scroll_accumulation(&self) -> bool {
+        self.accumulate_scroll
+    }
+
+    /// The vertical and horizontal bounds of a single scroll.
+    pub fn max_scroll_distances(&self) -> (u16, u16) {
+        (self.max_scroll_vertical, self.max_scroll_horizontal)
+    }
+
+    pub fn dpi_scale(&self) -> f32 {
+        self.dpi_scale
+    }
+
+    pub fn move_step(&self) -> u16 {
+        self.move_step
+    }
+
+    pub fn mouse_rate_limit(&self) -> Option<Duration> {
+        self.mouse_rate_limit
+    }
+
+    pub fn unicode_method(&self) -> UnicodeMethod {
+        self.unicode_method
+    }
+
+    pub fn unicode_char_delay_ms(&self) -> Option<u32> {
+        self.unicode_char_delay_ms
+    }
+
+    pub fn tap_gap(&self) -> u32 {
+        self.tap_gap_ms
+    }
+
+    pub fn skip_unheld_releases(&self) -> bool {
+        self.skip_unheld_releases
+    }
+
+    pub fn skip_held_clicks(&self) -> bool {
+        self.skip_held_clicks
+    }
+
+    pub fn swap_nav_buttons(&self) -> bool {
+        self.swap_nav_buttons
+    }
+
+    pub fn button_watchdog(&self) -> Option<Duration> {
+        self.button_watchdog
+    }
+
+    pub fn paste_preserves_clipboard(&self) -> bool {
+        self.paste_preserves_clipboard
+    }
+}
+
+impl KbdOut {
+    /// Enable or disable skipping releases of keys that are not held, which is off by default.
+    /// This avoids sending a second release when the processing loop releases a key twice. It
+    /// is opt-in because the processing loop also releases keys that were pressed before kanata
+    /// started, such as on startup, and those are never in the held keys.
+    pub fn set_skip_unheld_releases(&mut self, skip: bool) {
+        self.skip_unheld_releases = skip;
+    }
+
+    /// Enable or disable skipping presses of mouse buttons that are already held, which is on by
+    /// default. Mouse buttons do not auto-repeat, so a second press is never a real transition.
+    pub fn set_skip_held_clicks(&mut self, skip: bool) {
+        self.skip_held_clicks = skip;
+    }
+
+    /// Enable or disable natural scrolling, which reverses the direction of every scroll.
+    pub fn set_invert_scroll(&mut self, invert: bool) {
+        self.invert_scroll = invert;
+    }
+
+    /// Set the maximum distance of a single scroll per axis, so that e.g. horizontal tilt scrolling
+    /// can be limited separately. Scrolls above the maximum fail instead of being sent. Values
+    /// above `i16::MAX` cannot be represented in a wheel stroke and behave as `i16::MAX`, so
+    /// longer scrolls fail rather than being cut short.
+    #[allow(dead_code)] // The configuration already bounds scroll distances.
+    pub fn set_max_scroll_distance(&mut self, vertical: u16, horizontal: u16) {
+        self.max_scroll_vertical = vertical;
+        self.max_scroll_horizontal = horizontal;
+    }
+
+    fn max_scroll_distance(&self, direction: MWheelDirection) -> u16 {
+        let max = match direction {
+            MWheelDirection::Up | MWheelDirection::Down => self.max_scroll_vertical,
+            MWheelDirection::Left | MWheelDirection::Right => self.max_scroll_horizontal,
+        };
+        max.min(MAX_STROKE_SCROLL_DISTANCE)
+    }
+
+    /// Set the acceleration profile used by [`KbdOut::move_mouse_accel`].
+    #[allow(dead_code)] // Only tunes move_mouse_accel.
+    pub fn set_mouse_accel(&mut self, accel: AccelRamp) {
+        self.mouse_accel = accel;
+    }
+
+    /// Set the factor that the inertia velocity is multiplied by on every tick. It is clamped to
+    /// `[0, 0.99]` so that the movement always comes to a stop.
+    #[allow(dead_code)] // Inertia is not exposed to the configuration.
+    pub fn set_inertia_decay(&mut self, decay: f32) {
+        self.inertia_decay = decay.clamp(0.0, 0.99);
+    }
+
+    /// Set the acceleration profile used by [`KbdOut::scroll_accel`].
+    #[allow(dead_code)] // Only tunes scroll_accel.
+    pub fn set_scroll_accel(&mut self, accel: AccelRamp) {
+        self.scroll_accel = accel;
+    }
+
+    /// Set the delay between the characters sent with `send_unicode` or `send_unicode_string`.
+    /// Characters that have to wait are sent by [`KbdOut::tick`], so the delay does not block,
+    /// but other output sent in the meantime goes out between them. `None` or `Some(0)` sends
+    /// characters without delay.
+    pub fn set_unicode_char_delay(&mut self, delay_ms: Option<u32>) {
+        self.unicode_char_delay_ms = delay_ms.filter(|d| *d > 0);
+    }
+
+    /// Choose how the unicode output methods type characters, see [`UnicodeMethod`].
+    pub fn set_unicode_method(&mut self, method: UnicodeMethod) {
+        self.unicode_method = method;
+    }
+
+    /// Set the time between the press and release sent by [`KbdOut::tap_key`] and
+    /// [`KbdOut::tap_btn`], for remote desktop and VM connections that drop a press and release
+    /// that arrive together. The release is sent by [`KbdOut::tick`], so this does not block.
+    /// The default of 0 sends both back-to-back.
+    pub fn set_tap_gap(&mut self, gap_ms: u32) {
+        self.tap_gap_ms = gap_ms;
+    }
+
+    pub fn write(&mut self, event: InputEvent) -> Result<(), KbdOutError> {
+        self.flush_pending_move(false)?;
+        self.send(OutEvent::Stroke(event))
+    }
+
+    /// Send `stroke` to the driver exactly as given, bypassing the typed helpers. This is an escape
+    /// hatch for strokes that kanata does not otherwise model, such as unusual mouse flags or a
+    /// specific `information` value. The caller is responsible for the stroke being valid; held
+    /// key and button tracking is not updated.
+    #[allow(dead_code)] // Escape hatch; everything the processing loop sends is modelled.
+    pub fn write_raw_stroke(&mut self, stroke: Stroke) -> Result<(), KbdOutError> {
+        self.write(InputEvent(stroke))
+    }
+
+    /// Replay `events`, each followed by its delay in milliseconds, e.g. a recorded macro. Events
+    /// up to the first non-zero delay are sent right away and the rest are scheduled, so this
+    /// does not block; they go out from [`KbdOut::tick`]. Like [`KbdOut::write`], held key and
+    /// button tracking is not updated.
+    #[allow(dead_code)] // Dynamic macros are replayed by the processing loop itself.
+    pub fn play_sequence(&mut self, events: &[(InputEvent, u32)]) -> Result<(), KbdOutError> {
+        let mut offset_ms = 0u32;
+        for &(event, delay_ms) in events {
+            if offset_ms == 0 {
+                self.write(event)?;
+            } else {
+                self.schedule(offset_ms, ScheduledAction::Write(event));
+            }
+            offset_ms = offset_ms.saturating_add(delay_ms);
+        }
+        Ok(())
+    }
+
+    /// Advance the output clock by one millisecond, send any scheduled output that is now due and
+    /// flush pending mouse movement. The processing loop calls this once per millisecond tick.
+    /// Everything that is due is sent even if some of it fails, and the first error is returned.
+    pub fn tick(&mut self) -> Result<(), KbdOutError> {
+        self.clock_ms += 1;
+        let now = self.clock_ms;
+        let mut due = vec![];
+        self.scheduled.retain(|s| {
+            let is_due = s.due_ms <= now;
+            if is_due {
+                due.push(*s);
+            }
+            !is_due
+        });
+        due.sort_by_key(|s| s.due_ms);
+        // A failed action does not hold back the others, which may release held keys.
+        let mut res = Ok(());
+        for s in due {
+            res = res.and(self.run_scheduled(s.action));
+        }
+        res.and(self.tick_watchdog())
+            .and(self.tick_inertia())
+            .and(self.flush())
+    }
+
+    fn schedule(&mut self, delay_ms: u32, action: ScheduledAction) {
+        self.scheduled.push(Scheduled {
+            due_ms: self.clock_ms + u64::from(delay_ms),
+            action,
+        });
+    }
+
+    fn run_scheduled(&mut self, action: ScheduledAction) -> Result<(), KbdOutError> {
+        match action {
+            ScheduledAction::ReleaseKey(key) => self.release_key(key),
+            ScheduledAction::ReleaseBtn(btn) => self.release_btn(btn),
+            ScheduledAction::Click(btn) => {
+                self.click_btn(btn)?;
+                self.release_btn(btn)
+            }
+            ScheduledAction::RestoreClipboard => match self.saved_clipboard.take() {
+                Some(text) => self.set_clipboard_text(&text),
+                None => Ok(()),
+            },
+            ScheduledAction::Write(event) => self.write(event),
+            ScheduledAction::Repeat { key, interval_ms } => {
+                self.write_key(key, KeyValue::Repeat)?;
+                self.schedule(interval_ms, action);
+                Ok(())
+            }
+            ScheduledAction::Unicode(c) => self.type_unicode(c),
+        }
+    }
+
+    /// Drop all scheduled output and stop inertia, without sending anything. Keys and buttons
+    /// that were waiting for a delayed release stay held, and a clipboard that a paste was going
+    /// to restore keeps the pasted text. Use [`KbdOut::cancel_scheduled_with_release`] to send
+    /// the releases instead.
+    #[allow(dead_code)] // Scheduled output is otherwise only dropped by reset and suspend.
+    pub fn cancel_scheduled(&mut self) {
+        self.scheduled.clear();
+        self.saved_clipboard = None;
+        self.inertia = None;
+    }
+
+    /// Stop inertia and send the scheduled releases and clipboard restores right away, in the
+    /// order they were due. Keys held with [`KbdOut::hold_repeat`] are released. Other scheduled
+    /// output, such as the second click of [`KbdOut::double_click`] or the rest of a
+    /// [`KbdOut::play_sequence`], is dropped. Unlike [`KbdOut::cancel_scheduled`] this leaves
+    /// nothing held down.
+    #[allow(dead_code)] // Like cancel_scheduled, no action calls it yet.
+    pub fn cancel_scheduled_with_release(&mut self) -> Result<(), KbdOutError> {
+        self.inertia = None;
+        let mut pending = std::mem::take(&mut self.scheduled);
+        pending.sort_by_key(|s| s.due_ms);
+        for s in pending {
+            match s.action {
+                ScheduledAction::Click(_)
+                | ScheduledAction::Write(_)
+                | ScheduledAction::Unicode(_) => {}
+                ScheduledAction::Repeat { key, .. } => self.release_key(key)?,
+                action => self.run_scheduled(action)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether any output has been accepted but not yet sent: scheduled output such as the
+    /// release of [`KbdOut::hold_key`], inertia movement, relative movement waiting for a flush
+    /// or held buttons that the button watchdog will release. Strokes are otherwise written
+    /// synchronously, so once this is false everything that was asked for has reached the driver.
+    /// The processing loop keeps ticking while this is true.
+    pub fn has_pending_output(&self) -> bool {
+        !self.scheduled.is_empty()
+            || self.inertia.is_some()
+            || self.pending_move != (0, 0)
+            || (self.button_watchdog.is_some() && !self.held_btns.is_empty())
+    }
+
+    /// Send the relative mouse movement accumulated since the last flush as a single stroke.
+    /// [`KbdOut::tick`] calls this at the end of every tick. With a mouse rate limit, the
+    /// movement is kept for a later flush if the previous one was sent too recently.
+    pub fn flush(&mut self) -> Result<(), KbdOutError> {
+        self.flush_pending_move(true)
+    }
+
+    /// Send the pending movement. Other events flush it with `rate_limited` false, since the
+    /// movement must reach the OS before they do.
+    fn flush_pending_move(&mut self, rate_limited: bool) -> Result<(), KbdOutError> {
+        if self.pending_move == (0, 0) {
+            return Ok(());
+        }
+        let now = Instant::now();
+        if let (true, Some(limit), Some(last)) =
+            (rate_limited, self.mouse_rate_limit, self.last_move_sent)
+        {
+            if now.duration_since(last) < limit {
+                return Ok(());
+            }
+        }
+        let (dx, dy) = std::mem::take(&mut self.pending_move);
+        let (dx, dy) = self.stepped(dx, dy);
+        if (dx, dy) == (0, 0) {
+            return Ok(());
+        }
+        self.last_move_sent = Some(now);
+        self.send(OutEvent::Stroke(InputEvent::from_mouse_move_xy(dx, dy)))
+    }
+
+    /// Rounds a relative move towards zero to whole steps of the move step, carrying the rest
+    /// forward to the next move.
+    fn stepped(&mut self, dx: i32, dy: i32) -> (i32, i32) {
+        if self.move_step == 1 {
+            return (dx, dy);
+        }
+        let step = i32::from(self.move_step);
+        let round = |distance: i32, remainder: &mut i32| {
+            let total = distance.saturating_add(*remainder);
+            *remainder = total % step;
+            total - *remainder
+        };
+        (
+            round(dx, &mut self.move_step_remainder.0),
+            round(dy, &mut self.move_step_remainder.1),
+        )
+    }
+
+    /// Send relative mouse movement only in multiples of `step` pixels on each axis, carrying
+    /// smaller amounts forward until a whole step has built up. This trades smoothness for
+    /// fewer, larger moves. The default is 1, and 0 is treated as 1.
+    pub fn set_move_step(&mut self, step: u16) {
+        self.move_step = step.max(1);
+        self.move_step_remainder = (0, 0);
+    }
+
+    /// Set the minimum interval between relative mouse movement strokes sent by
+    /// [`KbdOut::flush`]. Movement within the interval is combined into the next stroke instead
+    /// of being dropped. Key and mouse button events are never rate-limited, so that no press
+    /// is lost.
+    pub fn set_mouse_rate_limit(&mut self, min_interval: Option<Duration>) {
+        self.mouse_rate_limit = min_interval;
+    }
+
+    fn queue_move(&mut self, event: InputEvent) -> Result<(), KbdOutError> {
+        if let Stroke::Mouse { x, y, .. } = event.0 {
+            let (x, y) = self.dpi_scaled(x, y);
+            self.pending_move.0 = self.pending_move.0.saturating_add(x);
+            self.pending_move.1 = self.pending_move.1.saturating_add(y);
+        }
+        Ok(())
+    }
+
+    /// Applies the DPI scale to a relative move, rounding to whole pixels and carrying the
+    /// rounding error forward to the next move.
+    fn dpi_scaled(&mut self, x: i32, y: i32) -> (i32, i32) {
+        if self.dpi_scale == 1.0 {
+            return (x, y);
+        }
+        let scale = |distance: i32, remainder: &mut f32| {
+            let scaled = distance as f32 * self.dpi_scale + *remainder;
+            let pixels = saturating_pixels(scaled);
+            // Only keep a rounding error, not what was cut off by saturating.
+            *remainder = (scaled - pixels as f32).clamp(-0.5, 0.5);
+            pixels
+        };
+        (
+            scale(x, &mut self.dpi_remainder.0),
+            scale(y, &mut self.dpi_remainder.1),
+        )
+    }
+
+    /// Multiply relative mouse movement distances by `scale`, so that a configuration behaves the
+    /// same on displays of different DPI. A scale that is not a positive number is treated as 1.
+    pub fn set_dpi_scale(&mut self, scale: f32) {
+        self.dpi_scale = if scale.is_finite() && scale > 0.0 {
+            scale
+        } else {
+            1.0
+        };
+        self.dpi_remainder = (0.0, 0.0);
+    }
+
+    /// Send the raw virtual key `code`. A `Repeat` is sent as another key down, as the OS sends
+    /// for a held key, and keeps the key held.
+    pub fn write_code(&mut self, code: u32, value: KeyValue) -> Result<(), KbdOutError> {
+        self.flush_pending_move(false)?;
+        self.send(OutEvent::VirtualKey {
+            code: code as u16,
+            value,
+        })?;
+        match value {
+            KeyValue::Press | KeyValue::Repeat => self.held_codes.insert(code),
+            KeyValue::Release => self.held_codes.remove(&code),
+            KeyValue::Tap => false,
+        };
+        Ok(())
+    }
+
+    /// Send the set 1 `scancode`, with the E0 prefix if `extended`, for keys that have no
+    /// [`OsCode`]. Like [`KbdOut::write_raw_stroke`] the key is not tracked as held.
+    #[allow(dead_code)] // Scancodes without an OsCode cannot be named in the configuration.
+    pub fn write_extended_scancode(
+        &mut self,
+        scancode: u16,
+        extended: bool,
+        value: KeyValue,
+    ) -> Result<(), KbdOutError> {
+        let code = ScanCode::try_from(scancode)
+            .map_err(|_| KbdOutError::InvalidInput(format!("unknown scancode {scancode:#04x}")))?;
+        let mut state = match value {
+            KeyValue::Press | KeyValue::Repeat => KeyState::DOWN,
+            KeyValue::Release => KeyState::UP,
+            KeyValue::Tap => {
+                return Err(KbdOutError::InvalidInput(
+                    "a scancode must be pressed or released, not tapped".into(),
+                ))
+            }
+        };
+        state.set(KeyState::E0, extended);
+        let information = match value {
+            KeyValue::Repeat => KANATA_SYNTHETIC_MARK | KANATA_REPEAT_FLAG,
+            _ => KANATA_SYNTHETIC_MARK,
+        };
+        self.write(InputEvent(Stroke::Keyboard {
+            code,
+            state,
+            information,
+        }))
+    }
+
+    /// Send the set 1 `scancode` without the E0 prefix, as an escape hatch for keys that kanata
+    /// does not model, e.g. ones read by legacy apps. Values that the interception driver has no
+    /// [`ScanCode`] for are rejected. See [`KbdOut::write_extended_scancode`].
+    #[allow(dead_code)] // Like write_extended_scancode, for codes that have no OsCode.
+    pub fn write_raw_scancode(
+        &mut self,
+        scancode: u16,
+        value: KeyValue,
+    ) -> Result<(), KbdOutError> {
+        self.write_extended_scancode(scancode, false, value)
+    }
+
+    /// Press the raw virtual key `code`, for keys that have no [`OsCode`].
+    pub fn press_code(&mut self, code: u32) -> Result<(), KbdOutError> {
+        self.write_code(code, KeyValue::Press)
+    }
+
+    /// Release the raw virtual key `code`.
+    pub fn release_code(&mut self, code: u32) -> Result<(), KbdOutError> {
+        self.write_code(code, KeyValue::Release)
+    }
+
+    /// Send `key`. A `Repeat` is sent as a key down stroke marked with [`KANATA_REPEAT_FLAG`] and
+    /// keeps the key held. Repeats are only meaningful for keys; mouse buttons and wheels are
+    /// sent with the mouse methods, which take no [`KeyValue`]. A key that has no interception
+    /// scan code is logged and dropped, so that a config using it keeps running; use
+    /// [`KbdOut::try_write_key`] to get the error instead.
+    pub fn write_key(&mut self, key: OsCode, value: KeyValue) -> Result<(), KbdOutError> {
+        match self.try_write_key(key, value) {
+            // A `_` over a mouse wheel or movement input sends its code back, at the rate of the
+            // mouse reports.
+            Err(KbdOutError::Unmapped(e)) if is_mouse_code(key) => {
+                log::trace!("dropping mouse code: {e}");
+                Ok(())
+            }
+            Err(KbdOutError::Unmapped(e)) => {
+                log::error!("Trying to send unmapped oscode, dropping it: {e}");
+                Ok(())
+            }
+            res => res,
+        }
+    }
+
+    /// Like [`KbdOut::write_key`], but fails with [`KbdOutError::Unmapped`] for a key that has no
+    /// interception scan code.
+    pub fn try_write_key(&mut self, key: OsCode, value: KeyValue) -> Result<(), KbdOutError> {
+        debug_assert!(
+            !(value == KeyValue::Repeat && is_mouse_code(key)),
+            "repeat of mouse code {key:?}"
+        );
+        if value == KeyValue::Release && self.skip_unheld_releases && !self.held_keys.contains(&key)
+        {
+            log::debug!("not releasing {key:?}, which is not held");
+            return Ok(());
+        }
+        self.write(InputEvent::from_oscode(key, value)?)?;
+        match value {
+            KeyValue::Press | KeyValue::Repeat => self.held_keys.insert(key),
+            KeyValue::Release => self.held_keys.remove(&key),
+            KeyValue::Tap => false,
+        };
+        Ok(())
+    }
+
+    pub fn press_key(&mut self, key: OsCode) -> Result<(), KbdOutError> {
+        self.write_key(key, KeyValue::Press)
+    }
+
+    pub fn release_key(&mut self, key: OsCode) -> Result<(), KbdOutError> {
+        self.write_key(key, KeyValue::Release)
+    }
+
+    /// Press the key called `name` in the configuration, e.g. `lsft` or a name added with
+    /// `defcustomkeys`, for control surfaces that refer to keys by name.
+    #[allow(dead_code)] // Key names are resolved by the parser before they reach output.
+    pub fn press_key_named(&mut self, name: &str) -> Result<(), KbdOutError> {
+        let key = str_to_oscode(name)
+            .ok_or_else(|| KbdOutError::InvalidInput(format!("unknown key name: {name}")))?;
+        self.press_key(key)
+    }
+
+    /// Press `key` and keep sending repeats of it, the first after `initial_delay_ms` and then
+    /// every `repeat_ms`, until [`KbdOut::stop_repeat`]. The repeats are sent by
+    /// [`KbdOut::tick`], so this does not block. This drives auto-repeat for keys that the OS
+    /// does not repeat, such as remapped ones. A `repeat_ms` of 0 repeats on every tick.
+    #[allow(dead_code)] // Key repeat is generated by the processing loop.
+    pub fn hold_repeat(
+        &mut self,
+        key: OsCode,
+        initial_delay_ms: u32,
+        repeat_ms: u32,
+    ) -> Result<(), KbdOutError> {
+        self.stop_repeating(key);
+        self.press_key(key)?;
+        self.schedule(
+            initial_delay_ms.max(1),
+            ScheduledAction::Repeat {
+                key,
+                interval_ms: repeat_ms.max(1),
+            },
+        );
+        Ok(())
+    }
+
+    /// Stop the repeats started by [`KbdOut::hold_repeat`] and release `key`.
+    #[allow(dead_code)] // Only stops a hold_repeat.
+    pub fn stop_repeat(&mut self, key: OsCode) -> Result<(), KbdOutError> {
+        self.stop_repeating(key);
+        self.release_key(key)
+    }
+
+    fn stop_repeating(&mut self, key: OsCode) {
+        self.scheduled.retain(|s| {
+            !matches!(s.action, ScheduledAction::Repeat { key: repeating, .. } if repeating == key)
+        });
+    }
+
+    /// Press and release `key`, back-to-back unless a tap gap is set.
+    pub fn tap_key(&mut self, key: OsCode) -> Result<(), KbdOutError> {
+        self.hold_key(key, self.tap_gap_ms)
+    }
+
+    /// Press `keys` in order and then release them in reverse order, for a shortcut such as
+    /// ctrl+shift+esc. Keys that are already held are left as they are, so that they stay held
+    /// afterwards.
+    #[allow(dead_code)] // The processing loop presses chords key by key.
+    pub fn tap_chord(&mut self, keys: &[OsCode]) -> Result<(), KbdOutError> {
+        let transient: Vec<_> = keys
+            .iter()
+            .copied()
+            .filter(|k| !self.held_keys.contains(k))
+            .collect();
+        for key in &transient {
+            self.press_key(*key)?;
+        }
+        for key in transient.iter().rev() {
+            self.release_key(*key)?;
+        }
+        Ok(())
+    }
+
+    /// Tap a key combination written with the modifier prefixes of the configuration, e.g.
+    /// `C-S-a` for ctrl+shift+a: `C-` ctrl, `S-` shift, `A-` alt, `M-` meta and `RA-` or `AG-`
+    /// AltGr. The modifiers are pressed, the key is tapped, then the modifiers are released.
+    #[allow(dead_code)] // No action takes a combo string yet.
+    pub fn tap_combo(&mut self, combo: &str) -> Result<(), KbdOutError> {
+        let invalid = KbdOutError::InvalidInput;
+        let (mods, key) = kanata_parser::cfg::parse_mod_prefix(combo)
+            .map_err(|e| invalid(format!("invalid key combination {combo:?}: {}", e.msg)))?;
+        let key = str_to_oscode(key)
+            .ok_or_else(|| invalid(format!("unknown key {key:?} in combination {combo:?}")))?;
+        let mut keys: Vec<OsCode> = mods.into_iter().map(OsCode::from).collect();
+        keys.push(key);
+        self.tap_chord(&keys)
+    }
+
+    /// Press `key` and release it `duration_ms` later. This does not block: the release is sent
+    /// by [`KbdOut::tick`] once the duration has elapsed. A duration of 0 releases immediately.
+    pub fn hold_key(&mut self, key: OsCode, duration_ms: u32) -> Result<(), KbdOutError> {
+        self.press_key(key)?;
+        if duration_ms == 0 {
+            return self.release_key(key);
+        }
+        self.schedule(duration_ms, ScheduledAction::ReleaseKey(key));
+        Ok(())
+    }
+
+    /// Swap the forward and backward mouse buttons, for mice that report them reversed. This
+    /// only affects the strokes that are sent; the buttons are still tracked by their name.
+    pub fn set_swap_nav_buttons(&mut self, swap: bool) {
+        self.swap_nav_buttons = swap;
+    }
+
+    /// The button whose state is sent for `btn`.
+    fn output_btn(&self, btn: Btn) -> Btn {
+        match (self.swap_nav_buttons, btn) {
+            (true, Btn::Forward) => Btn::Backward,
+            (true, Btn::Backward) => Btn::Forward,
+            (_, btn) => btn,
+        }
+    }
+
+    pub fn click_btn(&mut self, btn: Btn) -> Result<(), KbdOutError> {
+        log::debug!("click btn: {:?}", btn);
+        if self.skip_held_clicks && self.held_btns.contains(&btn) {
+            log::debug!("not clicking {btn:?}, which is already held");
+            self.btn_refreshed.insert(btn, self.clock_ms);
+            return Ok(());
+        }
+        let Some(event) = self.btn_event(btn, false) else {
+            return Ok(());
+        };
+        self.btn_refreshed.insert(btn, self.clock_ms);
+        self.write(event)?;
+        self.held_btns.insert(btn);
+        Ok(())
+    }
+
+    pub fn release_btn(&mut self, btn: Btn) -> Result<(), KbdOutError> {
+        log::debug!("release btn: {:?}", btn);
+        let Some(event) = self.btn_event(btn, true) else {
+            return Ok(());
+        };
+        self.write(event)?;
+        self.held_btns.remove(&btn);
+        self.btn_refreshed.remove(&btn);
+        Ok(())
+    }
+
+    /// The stroke for `btn`, or `None` with a warning for a button that the driver cannot send,
+    /// so that a config using it keeps running like it does with the other Windows backend.
+    fn btn_event(&self, btn: Btn, is_up: bool) -> Option<InputEvent> {
+        match InputEvent::from_mouse_btn(self.output_btn(btn), is_up) {
+            Ok(event) => Some(event),
+            Err(e) => {
+                log::warn!("{e}, ignoring");
+                None
+            }
+        }
+    }
+
+    /// Release mouse buttons that have been held for longer than `timeout`, measured in
+    /// [`KbdOut::tick`]s, so that a binding that never releases cannot leave a button stuck
+    /// down. Clicking a held button again refreshes it. `None`, the default, turns this off.
+    pub fn set_button_watchdog(&mut self, timeout: Option<Duration>) {
+        self.button_watchdog = timeout;
+    }
+
+    /// Release the held mouse buttons whose watchdog timeout has passed.
+    fn tick_watchdog(&mut self) -> Result<(), KbdOutError> {
+        let timeout = match self.button_watchdog {
+            Some(timeout) => timeout,
+            None => return Ok(()),
+        };
+        let timeout_ms = u64::try_from(timeout.as_millis()).unwrap_or(u64::MAX);
+        let now = self.clock_ms;
+        let stuck: Vec<_> = self
+            .held_btns
+            .iter()
+            .copied()
+            .filter(|btn| {
+                self.btn_refreshed
+                    .get(btn)
+                    .is_some_and(|&refreshed| now - refreshed >= timeout_ms)
+            })
+            .collect();
+        for btn in stuck {
+            log::warn!("releasing {btn:?}, which has been held for longer than {timeout:?}");
+            self.release_btn(btn)?;
+        }
+        Ok(())
+    }
+
+    /// Start or stop the autoscroll mode of apps that scroll while the wheel is held down, by
+    /// pressing or releasing the middle button. Nothing is sent if the middle button is already
+    /// in the requested state, so this can be called on every toggle of a layer or key.
+    #[allow(dead_code)] // Autoscroll is not an action yet.
+    pub fn wheel_autoscroll(&mut self, active: bool) -> Result<(), KbdOutError> {
+        match (active, self.held_btns.contains(&Btn::Mid)) {
+            (true, false) => self.click_btn(Btn::Mid),
+            (false, true) => self.release_btn(Btn::Mid),
+            _ => Ok(()),
+        }
+    }
+
+    /// Click `btn` while holding the modifier keys `mods`, e.g. for ctrl+click. The modifiers are
+    /// pressed in order and released in reverse order after the button. Modifiers that are
+    /// already held are left as they are, so that they stay held afterwards.
+    #[allow(dead_code)] // The processing loop presses modifiers as separate keys.
+    pub fn click_with_mods(&mut self, btn: Btn, mods: &[OsCode]) -> Result<(), KbdOutError> {
+        let transient: Vec<_> = mods
+            .iter()
+            .copied()
+            .filter(|m| !self.held_keys.contains(m))
+            .collect();
+        for m in &transient {
+            self.press_key(*m)?;
+        }
+        self.click_btn(btn)?;
+        self.release_btn(btn)?;
+        for m in transient.iter().rev() {
+            self.release_key(*m)?;
+        }
+        Ok(())
+    }
+
+    /// Press every button of `btns` in order and back-to-back, so that the OS sees a chord
+    /// partially pressed for as short a time as possible.
+    #[allow(dead_code)] // The processing loop presses buttons one at a time.
+    pub fn press_btns(&mut self, btns: &[Btn]) -> Result<(), KbdOutError> {
+        btns.iter().try_for_each(|btn| self.click_btn(*btn))
+    }
+
+    /// Release every button of `btns` in order.
+    #[allow(dead_code)] // The processing loop releases buttons one at a time.
+    pub fn release_btns(&mut self, btns: &[Btn]) -> Result<(), KbdOutError> {
+        btns.iter().try_for_each(|btn| self.release_btn(*btn))
+    }
+
+    /// Modifier keys that are currently held.
+    pub fn held_modifiers(&self) -> ModBits {
+        ModBits::KEYS
+            .into_iter()
+            .filter(|(_, key)| self.held_keys.contains(key))
+            .fold(ModBits::NONE, |mods, (bit, _)| mods | bit)
+    }
+
+    /// Press and release modifier keys so that exactly `mods` are held. Modifiers that are already
+    /// in the right state are left alone, and releases are sent before presses.
+    #[allow(dead_code)] // The processing loop keeps its own modifier state.
+    pub fn apply_modifiers(&mut self, mods: ModBits) -> Result<(), KbdOutError> {
+        let held = self.held_modifiers();
+        for key in ModBits(held.0 & !mods.0).keys() {
+            self.release_key(key)?;
+        }
+        for key in ModBits(mods.0 & !held.0).keys() {
+            self.press_key(key)?;
+        }
+        Ok(())
+    }
+
+    /// Keys that kanata has pressed and not yet released. This reflects the intended output state,
+    /// not the physical state of the input keyboard.
+    #[allow(dead_code)] // The processing loop keeps its own pressed keys.
+    pub fn held_keys(&self) -> &HashSet<OsCode> {
+        &self.held_keys
+    }
+
+    /// Release every key and mouse button that has an outstanding press, so that nothing is left
+    /// stuck down in the OS on shutdown or config reload.
+    pub fn release_all_held(&mut self) -> Result<(), KbdOutError> {
+        let keys: Vec<_> = self.held_keys.iter().copied().collect();
+        for key in keys {
+            self.release_key(key)?;
+        }
+        let codes: Vec<_> = self.held_codes.iter().copied().collect();
+        for code in codes {
+            self.release_code(code)?;
+        }
+        let btns: Vec<_> = self.held_btns.iter().copied().collect();
+        for btn in btns {
+            self.release_btn(btn)?;
+        }
+        Ok(())
+    }
+
+    /// Return the output state to how it is after construction, e.g. on config reload, so that
+    /// nothing from before carries over. This:
+    ///
+    /// - releases every held key, raw key code and mouse button, and so the held modifiers;
+    /// - restores the clipboard if a paste is waiting to restore it, and drops all other
+    ///   scheduled output, such as the releases of [`KbdOut::hold_key`] and the repeats of
+    ///   [`KbdOut::hold_repeat`];
+    /// - stops inertia and discards relative movement that has not been sent yet;
+    /// - zeroes the scroll accumulators and the carried DPI, polar and move step remainders.
+    ///
+    /// Settings, statistics, the screen metrics, the last absolute position and the tracked
+    /// [`KbdOut::virtual_position`] are kept.
+    pub fn reset(&mut self) -> Result<(), KbdOutError> {
+        self.release_all_held()?;
+        if let Some(text) = self.saved_clipboard.take() {
+            self.set_clipboard_text(&text)?;
+        }
+        self.scheduled.clear();
+        self.inertia = None;
+        self.pending_move = (0, 0);
+        self.hires_scroll = ScrollAccumulator::default();
+        self.scroll_remainder = ScrollAccumulator::default();
+        self.dpi_remainder = (0.0, 0.0);
+        self.polar_remainder = (0.0, 0.0);
+        self.move_step_remainder = (0, 0);
+        Ok(())
+    }
+
+    /// Press and release `btn`, holding it for `hold_ms` in between. This does not block: the
+    /// release is sent by [`KbdOut::tick`] once the hold has elapsed. A `hold_ms` of 0 sends both
+    /// strokes back-to-back, or the release after the tap gap if one is set.
+    #[allow(dead_code)] // Mouse taps come from the processing loop as a click and a release.
+    pub fn tap_btn(&mut self, btn: Btn, hold_ms: u32) -> Result<(), KbdOutError> {
+        self.click_btn(btn)?;
+        let delay_ms = match hold_ms {
+            0 => self.tap_gap_ms,
+            hold_ms => hold_ms,
+        };
+        match delay_ms {
+            0 => self.release_btn(btn),
+            delay_ms => {
+                self.schedule(delay_ms, ScheduledAction::ReleaseBtn(btn));
+                Ok(())
+            }
+        }
+    }
+
+    /// Double-click `btn`, with `gap_ms` between the two clicks. This does not block: the second
+    /// click is sent by [`KbdOut::tick`]. A gap of 0 uses half of the system double-click time,
+    /// since clicks that are the full double-click time apart are not recognized as a double-click.
+    #[allow(dead_code)] // No action sends a double click yet.
+    pub fn double_click(&mut self, btn: Btn, gap_ms: u32) -> Result<(), KbdOutError> {
+        let gap_ms = match gap_ms {
+            0 => super::double_click_time_ms() / 2,
+            gap_ms => gap_ms,
+        };
+        self.click_btn(btn)?;
+        self.release_btn(btn)?;
+        self.schedule(gap_ms, ScheduledAction::Click(btn));
+        Ok(())
+    }
+
+    /// Enable or disable sending only whole notches from [`KbdOut::scroll`], which is off by
+    /// default. When on, distances that are not a multiple of a notch carry their remainder over
+    /// to later scrolls on the same axis, so that many small scrolls, e.g. from a trackpad, add up
+    /// to their full distance in applications that ignore partial notches.
+    pub fn set_scroll_accumulation(&mut self, accumulate: bool) {
+        self.accumulate_scroll = accumulate;
+        self.scroll_remainder = ScrollAccumulator::default();
+    }
+
+    pub fn scroll(&mut self, direction: MWheelDirection, distance: u16) -> Result<(), KbdOutError> {
+        log::debug!("scroll: {direction:?} {distance:?}");
+        if !self.accumulate_scroll {
+            return self.send_scroll(direction, distance);
+        }
+        let distance = bounded_scroll_distance(
+            direction,
+            distance.into(),
+            self.max_scroll_distance(direction),
+        )?;
+        let notches =
+            self.scroll_remainder
+                .add_scaled(direction, distance.into(), WHEEL_DELTA.into());
+        self.send_scroll_notches(direction, notches)
+    }
+
+    /// Scroll by exactly `distance`, without accumulating.
+    fn send_scroll(
+        &mut self,
+        direction: MWheelDirection,
+        distance: u16,
+    ) -> Result<(), KbdOutError> {
+        self.write(InputEvent::from_mouse_scroll(
+            direction,
+            distance,
+            self.invert_scroll,
+            self.max_scroll_distance(direction),
+        )?)
+    }
+
+    /// Scroll `distance` on both axes at once, vertically first.
+    #[allow(dead_code)] // Scroll actions move along one axis at a time.
+    pub fn scroll_diagonal(
+        &mut self,
+        direction: MWheelDiagonal,
+        distance: u16,
+    ) -> Result<(), KbdOutError> {
+        let (vertical, horizontal) = direction.split();
+        self.scroll(vertical, distance)?;
+        self.scroll(horizontal, distance)
+    }
+
+    /// Scroll both axes at once, like a trackpad does. Positive `vertical` scrolls up and positive
+    /// `horizontal` scrolls right. A stroke only carries one wheel, so this sends a vertical and
+    /// then a horizontal wheel stroke; an axis with a distance of zero is not sent.
+    #[allow(dead_code)] // Like scroll_diagonal, for signed distances.
+    pub fn scroll_xy(&mut self, vertical: i16, horizontal: i16) -> Result<(), KbdOutError> {
+        if vertical != 0 {
+            let direction = if vertical > 0 {
+                MWheelDirection::Up
+            } else {
+                MWheelDirection::Down
+            };
+            self.scroll(direction, vertical.unsigned_abs())?;
+        }
+        if horizontal != 0 {
+            let direction = if horizontal > 0 {
+                MWheelDirection::Right
+            } else {
+                MWheelDirection::Left
+            };
+            self.scroll(direction, horizontal.unsigned_abs())?;
+        }
+        Ok(())
+    }
+
+    /// Scroll in `direction` by the distance that the acceleration profile gives for the `tick`th
+    /// tick that the scroll has been held. Returns the distance scrolled.
+    #[allow(dead_code)] // Scroll actions use a fixed distance per tick.
+    pub fn scroll_accel(
+        &mut self,
+        direction: MWheelDirection,
+        tick: u32,
+    ) -> Result<u16, KbdOutError> {
+        let distance = self.scroll_accel.distance_at(tick);
+        self.scroll(direction, distance)?;
+        Ok(distance)
+    }
+
+    /// Scroll by whole wheel notches, each being [`WHEEL_DELTA`] units of distance.
+    #[allow(dead_code)] // Scroll actions give their distance in wheel units, not notches.
+    pub fn scroll_notch(
+        &mut self,
+        direction: MWheelDirection,
+        notches: u16,
+    ) -> Result<(), KbdOutError> {
+        let distance = bounded_scroll_distance(
+            direction,
+            u64::from(notches) * u64::from(HI_RES_SCROLL_UNITS_IN_LO_RES),
+            self.max_scroll_distance(direction),
+        )?;
+        self.send_scroll(direction, distance)
+    }
+
+    /// Scroll by `pages` pages of [`PAGE_SCROLL_NOTCHES`] notches each. Large amounts are split
+    /// into several wheel events that each stay within the scroll distance bound.
+    #[allow(dead_code)] // Scroll actions give their distance in wheel units, not pages.
+    pub fn scroll_page(
+        &mut self,
+        direction: MWheelDirection,
+        pages: u16,
+    ) -> Result<(), KbdOutError> {
+        let notches = i64::from(pages) * i64::from(PAGE_SCROLL_NOTCHES);
+        let notches = match direction {
+            MWheelDirection::Up | MWheelDirection::Right => notches,
+            MWheelDirection::Down | MWheelDirection::Left => -notches,
+        };
+        self.send_scroll_notches(direction, notches)
+    }
+
+    /// Scroll by `pixels` wheel units in `direction`, or the opposite direction if negative,
+    /// without rounding to whole notches. Applications that support smooth scrolling move by the
+    /// fraction of a notch, but others ignore the event or wait until a whole notch has built up.
+    #[allow(dead_code)] // Scroll actions give their distance in wheel units, not pixels.
+    pub fn scroll_pixels(
+        &mut self,
+        direction: MWheelDirection,
+        pixels: i16,
+    ) -> Result<(), KbdOutError> {
+        let direction = match (direction, pixels >= 0) {
+            (direction, true) => direction,
+            (MWheelDirection::Up, false) => MWheelDirection::Down,
+            (MWheelDirection::Down, false) => MWheelDirection::Up,
+            (MWheelDirection::Left, false) => MWheelDirection::Right,
+            (MWheelDirection::Right, false) => MWheelDirection::Left,
+        };
+        match pixels {
+            0 => Ok(()),
+            pixels => self.send_scroll(direction, pixels.unsigned_abs()),
+        }
+    }
+
+    /// Scroll by `hundredths` of a wheel notch. Amounts smaller than a notch are accumulated per
+    /// axis and a wheel event is only sent once a whole notch has built up.
+    #[allow(dead_code)] // Nothing sends partial notches from a float distance yet.
+    pub fn scroll_hires(
+        &mut self,
+        direction: MWheelDirection,
+        hundredths: u32,
+    ) -> Result<(), KbdOutError> {
+        let notches = self.hires_scroll.add(direction, hundredths);
+        self.send_scroll_notches(direction, notches)
+    }
+
+    /// Send `notches` whole notches on the axis of `direction`, where positive is up or right.
+    fn send_scroll_notches(
+        &mut self,
+        direction: MWheelDirection,
+        notches: i64,
+    ) -> Result<(), KbdOutError> {
+        let direction = match (direction, notches >= 0) {
+            (MWheelDirection::Up | MWheelDirection::Down, true) => MWheelDirection::Up,
+            (MWheelDirection::Up | MWheelDirection::Down, false) => MWheelDirection::Down,
+            (MWheelDirection::Left | MWheelDirection::Right, true) => MWheelDirection::Right,
+            (MWheelDirection::Left | MWheelDirection::Right, false) => MWheelDirection::Left,
+        };
+        // Stay within the distance bound of the axis. Every event carries at least one notch, which
+        // fails if the bound is below a notch.
+        let max_notches_per_event =
+            u64::from((self.max_scroll_distance(direction) / HI_RES_SCROLL_UNITS_IN_LO_RES).max(1));
+        let mut remaining = notches.unsigned_abs();
+        while remaining > 0 {
+            let chunk = remaining.min(max_notches_per_event);
+            remaining -= chunk;
+            let distance = bounded_scroll_distance(
+                direction,
+                chunk * u64::from(HI_RES_SCROLL_UNITS_IN_LO_RES),
+                self.max_scroll_distance(direction),
+            )?;
+            self.send_scroll(direction, distance)?;
+        }
+        Ok(())
+    }
+
+    /// Send using VK_PACKET. VK_PACKET carries a single UTF-16 code unit, so characters outside
+    /// of the BMP are sent as a surrogate pair with a down/up packet for each code unit. With a
+    /// character delay set, the character is queued behind the characters still waiting for it.
+    pub fn send_unicode(&mut self, c: char) -> Result<(), KbdOutError> {
+        let delay_ms = match self.unicode_char_delay_ms {
+            Some(delay_ms) => u64::from(delay_ms),
+            None => return self.type_unicode(c),
+        };
+        let last = self
+            .scheduled
+            .iter()
+            .filter(|s| matches!(s.action, ScheduledAction::Unicode(_)))
+            .map(|s| s.due_ms)
+            .max()
+            .or(self.last_unicode_ms);
+        match last.map(|last| last + delay_ms) {
+            Some(due_ms) if due_ms > self.clock_ms => {
+                let wait_ms = u32::try_from(due_ms - self.clock_ms).unwrap_or(u32::MAX);
+                self.schedule(wait_ms, ScheduledAction::Unicode(c));
+                Ok(())
+            }
+            _ => self.type_unicode(c),
+        }
+    }
+
+    /// Type `c` right away, ignoring the character delay.
+    fn type_unicode(&mut self, c: char) -> Result<(), KbdOutError> {
+        self.flush_pending_move(false)?;
+        match self.key_scan(c) {
+            Some((vk, mods)) => self.type_key_scan(vk, mods)?,
+            None => {
+                for (unit, up) in unicode_packets(c) {
+                    self.send(OutEvent::Unicode { unit, up })?;
+                }
+            }
+        }
+        self.last_unicode_ms = Some(self.clock_ms);
+        Ok(())
+    }
+
+    /// The virtual key and modifiers that type `c` with [`UnicodeMethod::KeyScan`], if it is
+    /// selected and the layout has a key for `c`.
+    fn key_scan(&self, c: char) -> Option<(u16, u8)> {
+        if self.unicode_method != UnicodeMethod::KeyScan {
+            return None;
+        }
+        let mut units = [0; 2];
+        match c.encode_utf16(&mut units) {
+            [unit] => super::vk_key_scan(*unit),
+            _ => None,
+        }
+    }
+
+    fn type_key_scan(&mut self, vk: u16, mods: u8) -> Result<(), KbdOutError> {
+        let mods: Vec<u32> = [(1, VK_SHIFT), (2, VK_CONTROL), (4, VK_MENU)]
+            .into_iter()
+            .filter(|(bit, _)| mods & bit != 0)
+            .map(|(_, mod_vk)| mod_vk as u32)
+            .collect();
+        for &mod_vk in &mods {
+            self.press_code(mod_vk)?;
+        }
+        self.press_code(u32::from(vk))?;
+        self.release_code(u32::from(vk))?;
+        for &mod_vk in mods.iter().rev() {
+            self.release_code(mod_vk)?;
+        }
+        Ok(())
+    }
+
+    /// Send the character with the Unicode scalar value `cp`, e.g. from a hex codepoint in the
+    /// configuration. Surrogates and values above U+10FFFF are rejected.
+    #[allow(dead_code)] // Unicode output is given as a char by the processing loop.
+    pub fn send_codepoint(&mut self, cp: u32) -> Result<(), KbdOutError> {
+        let c = char::from_u32(cp).ok_or_else(|| {
+            KbdOutError::InvalidInput(format!("{cp:#x} is not a unicode scalar value"))
+        })?;
+        self.send_unicode(c)
+    }
+
+    /// Type `s` by putting it on the clipboard and sending ctrl+v. This is faster than
+    /// [`KbdOut::send_unicode_string`] for long text and works in applications that mishandle
+    /// VK_PACKET. Unless disabled with [`KbdOut::set_paste_preserves_clipboard`], the previous
+    /// clipboard text is restored shortly afterwards by [`KbdOut::tick`].
+    #[allow(dead_code)] // No action pastes through the clipboard yet.
+    pub fn paste_text(&mut self, s: &str) -> Result<(), KbdOutError> {
+        if self.suspended {
+            return Ok(());
+        }
+        let previous = match (self.paste_preserves_clipboard, self.saved_clipboard.take()) {
+            (false, _) => None,
+            // A restore from an earlier paste is still pending, and holds the original text.
+            (true, Some(saved)) => Some(saved),
+            (true, None) => self.clipboard_text()?,
+        };
+        self.set_clipboard_text(s)?;
+        self.click_key_with_ctrl(OsCode::KEY_V)?;
+        self.scheduled
+            .retain(|s| s.action != ScheduledAction::RestoreClipboard);
+        if previous.is_some() {
+            self.saved_clipboard = previous;
+            self.schedule(
+                CLIPBOARD_RESTORE_DELAY_MS,
+                ScheduledAction::RestoreClipboard,
+            );
+        }
+        Ok(())
+    }
+
+    /// Enable or disable restoring the previous clipboard text after [`KbdOut::paste_text`],
+    /// which is on by default.
+    #[allow(dead_code)] // Only affects paste_text.
+    pub fn set_paste_preserves_clipboard(&mut self, preserve: bool) {
+        self.paste_preserves_clipboard = preserve;
+    }
+
+    fn click_key_with_ctrl(&mut self, key: OsCode) -> Result<(), KbdOutError> {
+        let ctrl_held = self.held_keys.contains(&OsCode::KEY_LEFTCTRL);
+        if !ctrl_held {
+            self.press_key(OsCode::KEY_LEFTCTRL)?;
+        }
+        self.tap_key(key)?;
+        if !ctrl_held {
+            self.release_key(OsCode::KEY_LEFTCTRL)?;
+        }
+        Ok(())
+    }
+
+    fn clipboard_text(&self) -> Result<Option<String>, KbdOutError> {
+        match self.simulated {
+            Some(_) => Ok(self.simulated_clipboard.clone()),
+            None => super::clipboard_text().map_err(KbdOutError::Clipboard),
+        }
+    }
+
+    fn set_clipboard_text(&mut self, text: &str) -> Result<(), KbdOutError> {
+        match self.simulated {
+            Some(_) => {
+                self.simulated_clipboard = Some(text.to_owned());
+                Ok(())
+            }
+            None => super::set_clipboard_text(text).map_err(KbdOutError::Clipboard),
+        }
+    }
+
+    /// Type `s` by pressing and releasing real keys, holding shift where needed, for applications
+    /// such as games that ignore VK_PACKET. This assumes a US layout. Characters without a key on
+    /// that layout, including all non-ASCII characters, are sent like [`KbdOut::send_unicode`]
+    /// but without the character delay.
+    #[allow(dead_code)] // The processing loop types text one character at a time.
+    pub fn type_ascii(&mut self, s: &str) -> Result<(), KbdOutError> {
+        for c in s.chars() {
+            match ascii_to_key(c) {
+                Some((key, true)) => {
+                    self.press_key(OsCode::KEY_LEFTSHIFT)?;
+                    self.tap_key(key)?;
+                    self.release_key(OsCode::KEY_LEFTSHIFT)?;
+                }
+                Some((key, false)) => self.tap_key(key)?,
+                // Not delayed, which would send it after the keys that follow it.
+                None => self.type_unicode(c)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Send a whole string using VK_PACKET, stopping at the first failure. Unless a per-character
+    /// delay is configured, the string is encoded to UTF-16 once and sent in a single pass.
+    #[allow(dead_code)] // The processing loop types text one character at a time.
+    pub fn send_unicode_string(&mut self, s: &str) -> Result<(), KbdOutError> {
+        self.flush_pending_move(false)?;
+        if self.unicode_char_delay_ms.is_some() || self.unicode_method == UnicodeMethod::KeyScan {
+            return s.chars().try_for_each(|c| self.send_unicode(c));
+        }
+        for (unit, up) in unicode_string_packets(s) {
+            self.send(OutEvent::Unicode { unit, up })?;
+        }
+        Ok(())
+    }
+
+    /// Tap `trigger` with [`KbdOut::tap_key`] and then type `text` with [`KbdOut::type_ascii`],
+    /// e.g. tab and a password for a login form. Through a [`KbdOutSender`] no other call can run
+    /// between the two. With a tap gap, the releases are sent by later ticks like those of any
+    /// other tap.
+    pub fn tap_then_type(&mut self, trigger: OsCode, text: &str) -> Result<(), KbdOutError> {
+        self.tap_key(trigger)?;
+        self.type_ascii(text)
+    }
+
+    pub fn move_mouse(&mut self, mv: CalculatedMouseMove) -> Result<(), KbdOutError> {
+        self.queue_move(InputEvent::from_mouse_move(mv.direction, mv.distance))
+    }
+
+    /// Keep the cursor moving in `direction` after the move key is released, starting at
+    /// `velocity` pixels per tick and slowing by the inertia decay on every tick. This replaces any
+    /// inertia that is still in progress.
+    #[allow(dead_code)] // Inertia is not exposed to the configuration.
+    pub fn start_inertia(&mut self, direction: MoveDirection, velocity: u16) {
+        self.inertia = Some(Inertia {
+            direction,
+            velocity: f32::from(velocity),
+        });
+    }
+
+    /// Stop any inertia movement in progress.
+    #[allow(dead_code)] // Only stops a start_inertia.
+    pub fn stop_inertia(&mut self) {
+        self.inertia = None;
+    }
+
+    /// Move the cursor by this tick's inertia distance. Returns whether the movement continues
+    /// on later ticks. [`KbdOut::tick`] calls this every tick.
+    pub fn tick_inertia(&mut self) -> Result<bool, KbdOutError> {
+        let inertia = match self.inertia.as_mut() {
+            Some(inertia) => inertia,
+            None => return Ok(false),
+        };
+        let distance = inertia.velocity.round() as u16;
+        if distance == 0 {
+            self.inertia = None;
+            return Ok(false);
+        }
+        let direction = inertia.direction;
+        inertia.velocity *= self.inertia_decay;
+        self.move_mouse(CalculatedMouseMove {
+            direction,
+            distance,
+        })?;
+        Ok(true)
+    }
+
+    /// Move the mouse in `direction` by the distance that the acceleration profile gives for the
+    /// `tick`th tick that the movement has been held. Returns the distance moved.
+    #[allow(dead_code)] // Movement actions do their own acceleration.
+    pub fn move_mouse_accel(
+        &mut self,
+        direction: MoveDirection,
+        tick: u32,
+    ) -> Result<u16, KbdOutError> {
+        let distance = self.mouse_accel.distance_at(tick);
+        self.move_mouse(CalculatedMouseMove {
+            direction,
+            distance,
+        })?;
+        Ok(distance)
+    }
+
+    /// Move the mouse by `dx` and `dy` at once, which avoids the staircase effect of moving each
+    /// axis with a separate event.
+    #[allow(dead_code)] // Movement actions move along one axis at a time.
+    pub fn move_mouse_xy(&mut self, dx: i32, dy: i32) -> Result<(), KbdOutError> {
+        self.queue_move(InputEvent::from_mouse_move_xy(dx, dy))
+    }
+
+    /// Move the mouse `distance` pixels at `angle_deg` degrees, counterclockwise from the right,
+    /// so 90 is up and 270 is down. The angle is normalized into 0-360 and the move is sent as a
+    /// single stroke. Fractions of a pixel are carried over to the next polar move, so that a
+    /// steady angle does not drift towards the nearest axis.
+    #[allow(dead_code)] // No action moves at an angle.
+    pub fn move_mouse_polar(&mut self, angle_deg: f32, distance: u16) -> Result<(), KbdOutError> {
+        let angle = angle_deg.rem_euclid(360.0).to_radians();
+        let distance = f32::from(distance);
+        let carry = |delta: f32, remainder: &mut f32| {
+            let delta = delta + *remainder;
+            let rounded = delta.round();
+            *remainder = delta - rounded;
+            rounded as i32
+        };
+        let dx = carry(distance * angle.cos(), &mut self.polar_remainder.0);
+        // Screen y grows downwards.
+        let dy = carry(-distance * angle.sin(), &mut self.polar_remainder.1);
+        if (dx, dy) == (0, 0) {
+            return Ok(());
+        }
+        self.move_mouse_xy(dx, dy)
+    }
+
+    /// Reset the idle timers of the OS without any visible effect, for anti-idle setups that
+    /// call this on a timer. This sends a single relative mouse move stroke of 0 pixels on both
+    /// axes, after any movement that was waiting to be flushed. Nothing is pressed, so no
+    /// application sees a key and the cursor does not move.
+    #[allow(dead_code)] // No idle keepalive is configured yet.
+    pub fn keepalive(&mut self) -> Result<(), KbdOutError> {
+        self.write(InputEvent::from_mouse_move_xy(0, 0))
+    }
+
+    /// Move the mouse by a `distance` that may be above what [`KbdOut::move_mouse`] accepts. The
+    /// distance is sent as strokes of at most `u16::MAX` pixels each.
+    #[allow(dead_code)] // Movement actions send distances that fit in a single stroke.
+    pub fn move_mouse_far(
+        &mut self,
+        direction: MoveDirection,
+        distance: u32,
+    ) -> Result<(), KbdOutError> {
+        let mut remaining = distance;
+        while remaining > 0 {
+            let chunk = remaining.min(u32::from(u16::MAX));
+            remaining -= chunk;
+            self.move_mouse(CalculatedMouseMove {
+                direction,
+                distance: chunk as u16,
+            })?;
+            self.flush_pending_move(false)?;
+        }
+        Ok(())
+    }
+
+    /// Move the mouse by `dx` and `dy` in `steps` separate strokes instead of one jump. The
+    /// remainder pixels are spread evenly over the steps so that the strokes add up to exactly
+    /// `dx` and `dy`. A `steps` of 0 is treated as 1.
+    #[allow(dead_code)] // The processing loop smooths movement over its ticks.
+    pub fn move_mouse_smooth(&mut self, dx: i32, dy: i32, steps: u32) -> Result<(), KbdOutError> {
+        let steps = i64::from(steps.max(1));
+        let progress = |total: i32, step: i64| (i64::from(total) * step / steps) as i32;
+        for step in 0..steps {
+            let step_dx = progress(dx, step + 1) - progress(dx, step);
+            let step_dy = progress(dy, step + 1) - progress(dy, step);
+            if (step_dx, step_dy) != (0, 0) {
+                self.write(InputEvent::from_mouse_move_xy(step_dx, step_dy))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Drag with `btn`: press it, move by `dx` and `dy` in `steps` strokes like
+    /// [`KbdOut::move_mouse_smooth`], then release it. The button is held for every move stroke.
+    #[allow(dead_code)] // A drag is a click, moves and a release from the processing loop.
+    pub fn drag(&mut self, btn: Btn, dx: i32, dy: i32, steps: u32) -> Result<(), KbdOutError> {
+        self.click_btn(btn)?;
+        self.move_mouse_smooth(dx, dy, steps)?;
+        self.release_btn(btn)
+    }
+
+    pub fn move_mouse_many(&mut self, moves: &[CalculatedMouseMove]) -> Result<(), KbdOutError> {
+        self.queue_move(InputEvent::from_mouse_move_many(moves))
+    }
+
+    /// Move the cursor to the normalized coordinates `x` and `y`, in the range 0-65535 across the
+    /// virtual screen. This forgets the position reported by [`KbdOut::last_absolute_position`].
+    pub fn set_mouse(&mut self, x: u16, y: u16) -> Result<(), KbdOutError> {
+        self.last_absolute = None;
+        self.box_position = None;
+        // Seeded again from the OS when next needed.
+        self.virtual_position = None;
+        self.write(InputEvent::from_mouse_set(x, y))
+    }
+
+    /// Move the cursor to the pixel coordinates `x` and `y` of the virtual desktop, which are
+    /// negative for monitors left of or above the primary one. Coordinates beyond the virtual
+    /// screen are clamped to its edges.
+    #[allow(dead_code)] // The processing loop moves the cursor to a position with set_mouse.
+    pub fn move_mouse_to(&mut self, x: i32, y: i32) -> Result<(), KbdOutError> {
+        let screen = *self.screen.get_or_insert_with(ScreenMetrics::query);
+        let (x, y) = screen.clamp(x, y);
+        self.write(InputEvent::from_mouse_set(
+            pixel_to_normalized(x, screen.width),
+            pixel_to_normalized(y, screen.height),
+        ))?;
+        self.last_absolute = Some((screen.left + x as i32, screen.top + y as i32));
+        self.virtual_position = self.last_absolute;
+        self.box_position = None;
+        Ok(())
+    }
+
+    /// Move the cursor by `dx` and `dy` pixels, but no further than the edges of `bounds`.
+    /// Interception only sends relative moves, so the position is tracked here: it starts at
+    /// the last [`KbdOut::move_mouse_to`] position, or at the centre of `bounds` if there is
+    /// none, and is clamped into `bounds` first. The movement is not DPI scaled. Movement of the
+    /// physical mouse is not seen, so a kiosk setup should position the cursor with
+    /// [`KbdOut::move_mouse_to`] before confining it. Returns the tracked position.
+    #[allow(dead_code)] // No action confines the cursor yet.
+    pub fn move_mouse_in_box(
+        &mut self,
+        dx: i32,
+        dy: i32,
+        bounds: Rect,
+    ) -> Result<(i32, i32), KbdOutError> {
+        let (x, y) = self
+            .box_position
+            .or(self.last_absolute)
+            .unwrap_or_else(|| bounds.center());
+        let (x, y) = bounds.clamp(x, y);
+        let (new_x, new_y) = bounds.clamp(x.saturating_add(dx), y.saturating_add(dy));
+        self.box_position = Some((new_x, new_y));
+        self.pending_move.0 = self.pending_move.0.saturating_add(new_x - x);
+        self.pending_move.1 = self.pending_move.1.saturating_add(new_y - y);
+        Ok((new_x, new_y))
+    }
+
+    /// Desktop coordinates that the last [`KbdOut::move_mouse_to`] moved the cursor to, clamped
+    /// to the screen. This is `None` before the first absolute move and after
+    /// [`KbdOut::set_mouse`], and does not follow relative movement or the physical mouse.
+    #[allow(dead_code)] // Only the tests read it back.
+    pub fn last_absolute_position(&self) -> Option<(i32, i32)> {
+        self.last_absolute
+    }
+
+    /// Desktop coordinates of the cursor as tracked by adding up the relative moves that were
+    /// sent, since interception moves are relative and do not report where the cursor ends up.
+    /// This lets features such as moving to the centre of a monitor be done with relative moves.
+    /// The position is seeded from the OS on first use and after [`KbdOut::set_mouse`], is set by
+    /// [`KbdOut::move_mouse_to`], and is kept within the screen once its metrics are known.
+    /// Movement of the physical mouse and Windows pointer acceleration are not seen, so call
+    /// [`KbdOut::resync_virtual_position`] before relying on it after either may have happened.
+    pub fn virtual_position(&mut self) -> (i32, i32) {
+        match self.virtual_position {
+            Some(position) => position,
+            None => self.resync_virtual_position(),
+        }
+    }
+
+    /// Query the cursor position from the OS and continue tracking from there. Returns the new
+    /// position, which falls back to the last [`KbdOut::move_mouse_to`] position or the origin if
+    /// the query fails, e.g. on the secure desktop, or when the output is simulated.
+    pub fn resync_virtual_position(&mut self) -> (i32, i32) {
+        let queried = match self.simulated {
+            Some(_) => None,
+            None => super::cursor_pos(),
+        };
+        let position = queried.or(self.last_absolute).unwrap_or_default();
+        self.virtual_position = Some(position);
+        position
+    }
+
+    fn track_relative_move(&mut self, dx: i32, dy: i32) {
+        let (x, y) = self.virtual_position();
+        let (x, y) = (x.saturating_add(dx), y.saturating_add(dy));
+        self.virtual_position = Some(match self.screen {
+            Some(screen) => {
+                let (x, y) = screen.clamp(x, y);
+                (screen.left + x as i32, screen.top + y as i32)
+            }
+            None => (x, y),
+        });
+    }
+
+    /// Query the virtual screen metrics used by [`KbdOut::move_mouse_to`] again. The metrics are
+    /// cached, so this should be called when the display configuration changes.
+    #[allow(dead_code)] // Nothing watches for display changes yet.
+    pub fn refresh_screen_metrics(&mut self) {
+        self.screen = Some(ScreenMetrics::query());
+    }
+
+    /// Use `screen` for [`KbdOut::move_mouse_to`] instead of the queried metrics.
+    #[allow(dead_code)] // Only tests override the queried screen size.
+    pub fn set_screen_metrics(&mut self, screen: ScreenMetrics) {
+        self.screen = Some(screen);
+    }
+}
+
+/// Builder for a [`KbdOut`] with settings other than the defaults. Each method applies the
+/// setting the same way as the corresponding `KbdOut::set_*` method.
+pub struct KbdOutBuilder(KbdOut);
+
+impl KbdOutBuilder {
+    pub fn new() -> Self {
+        Self(KbdOut::with_defaults())
+    }
+
+    pub fn build(self) -> Result<KbdOut, KbdOutError> {
+        Ok(self.0)
+    }
+}
+
+// The processing loop builds its KbdOut with the defaults, since the configuration has no
+// options for these yet.
+#[allow(dead_code)]
+impl KbdOutBuilder {
+    /// Send strokes to the interception `device`, see [`KbdOut::with_device`].
+    pub fn device(mut self, device: Device) -> Self {
+        self.0.device = Some(device);
+        self
+    }
+
+    /// Record output instead of sending it, see [`KbdOut::new_simulated`].
+    #[cfg(test)]
+    pub fn simulated(mut self) -> Self {
+        self.0.simulated = Some(Vec::new());
+        self
+    }
+
+    pub fn invert_scroll(mut self, invert: bool) -> Self {
+        self.0.set_invert_scroll(invert);
+        self
+    }
+
+    pub fn scroll_accumulation(mut self, accumulate: bool) -> Self {
+        self.0.set_scroll_accumulation(accumulate);
+        self
+    }
+
+    pub fn dpi_scale(mut self, scale: f32) -> Self {
+        self.0.set_dpi_scale(scale);
+        self
+    }
+
+    pub fn move_step(mut self, step: u16) -> Self {
+        self.0.set_move_step(step);
+        self
+    }
+
+    pub fn mouse_rate_limit(mut self, min_interval: Option<Duration>) -> Self {
+        self.0.set_mouse_rate_limit(min_interval);
+        self
+    }
+
+    pub fn button_watchdog(mut self, timeout: Option<Duration>) -> Self {
+        self.0.set_button_watchdog(timeout);
+        self
+    }
+
+    pub fn source_id(mut self, id: u16) -> Self {
+        self.0.set_source_id(id);
+        self
+    }
+
+    pub fn unicode_char_delay(mut self, delay_ms: Option<u32>) -> Self {
+        self.0.set_unicode_char_delay(delay_ms);
+        self
+    }
+
+    pub fn unicode_method(mut self, method: UnicodeMethod) -> Self {
+        self.0.set_unicode_method(method);
+        self
+    }
+
+    pub fn tap_gap(mut self, gap_ms: u32) -> Self {
+        self.0.set_tap_gap(gap_ms);
+        self
+    }
+
+    pub fn skip_unheld_releases(mut self, skip: bool) -> Self {
+        self.0.set_skip_unheld_releases(skip);
+        self
+    }
+
+    pub fn skip_held_clicks(mut self, skip: bool) -> Self {
+        self.0.set_skip_held_clicks(skip);
+        self
+    }
+
+    pub fn swap_nav_buttons(mut self, swap: bool) -> Self {
+        self.0.set_swap_nav_buttons(swap);
+        self
+    }
+}
+
+impl Default for KbdOutBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A [`KbdOut`] call sent to the thread of a [`KbdOutSender`].
+enum KbdOutCommand {
+    WriteKey(OsCode, KeyValue),
+    PressKey(OsCode),
+    ReleaseKey(OsCode),
+    TapKey(OsCode),
+    ClickBtn(Btn),
+    ReleaseBtn(Btn),
+    Scroll(MWheelDirection, u16),
+    MoveMouse(CalculatedMouseMove),
+    SetMouse(u16, u16),
+    SendUnicode(char),
+    TapThenType(OsCode, String),
+    Tick,
+    /// Flush pending movement, then signal the sender.
+    Barrier(std::sync::mpsc::Sender<()>),
+    /// Calls collected between [`KbdOutSender::begin_batch`] and [`KbdOutSender::flush`], run
+    /// back to back.
+    Batch(Vec<KbdOutCommand>),
+}
+
+/// A call run by the thread of a [`KbdOutSender`] that failed.
+#[derive(Debug)]
+#[allow(dead_code)] // Read by the owner of a KbdOutSender.
+pub struct FailedOutput {
+    /// The event that could not be written. `None` if the call failed before it had built any
+    /// output, e.g. for a scroll distance above the maximum.
+    pub event: Option<OutEvent>,
+    pub error: KbdOutError,
+}
+
+/// Number of queued calls at which [`KbdOutSender::is_backpressured`] reports that the thread of
+/// a [`KbdOutSender`] is not keeping up. Sending a stroke takes well under a millisecond, so this
+/// many calls waiting means output is about a quarter of a second or more behind. A batch counts
+/// as a single call.
+pub const KBD_OUT_BACKPRESSURE_DEPTH: usize = 256;
+
+/// Handle that drives a [`KbdOut`] owned by a dedicated thread, for callers such as async tasks
+/// that must not block on the driver. Each method queues the call and returns immediately; it
+/// only fails if the thread has stopped. Errors from sending the output are collected by the
+/// thread and returned by [`KbdOutSender::poll_errors`].
+///
+/// Calls can be batched, e.g. all output of one engine tick, so that they cost a single channel
+/// message and are written contiguously; see [`KbdOutSender::begin_batch`].
+#[allow(dead_code)] // kanata's own processing loop writes through its KbdOut directly.
+pub struct KbdOutSender {
+    tx: std::sync::mpsc::Sender<KbdOutCommand>,
+    /// Calls held back until [`KbdOutSender::flush`], if a batch has been started.
+    batch: Mutex<Option<Vec<KbdOutCommand>>>,
+    errors: std::sync::mpsc::Receiver<FailedOutput>,
+    /// Calls queued that the thread has not finished running.
+    depth: Arc<AtomicUsize>,
+    worker: std::thread::JoinHandle<KbdOut>,
+}
+
+#[allow(dead_code)]
+impl KbdOutSender {
+    /// Move `kbd_out` to a new thread that runs the calls sent through the returned handle.
+    pub fn spawn(mut kbd_out: KbdOut) -> Self {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let (errors_tx, errors) = std::sync::mpsc::channel();
+        let depth = Arc::new(AtomicUsize::new(0));
+        let worker_depth = depth.clone();
+        let worker = std::thread::spawn(move || {
+            for command in rx {
+                run_command(&mut kbd_out, command, &errors_tx);
+                worker_depth.fetch_sub(1, Ordering::SeqCst);
+            }
+            kbd_out
+        });
+        Self {
+            tx,
+            batch: Mutex::new(None),
+            errors,
+            depth,
+            worker,
+        }
+    }
+
+    /// The calls that have failed on the thread since the last poll, oldest first, each with the
+    /// event that was not written.
+    pub fn poll_errors(&self) -> Vec<FailedOutput> {
+        self.errors.try_iter().collect()
+    }
+
+    /// Number of queued calls that the thread has not finished running yet.
+    pub fn queue_depth(&self) -> usize {
+        self.depth.load(Ordering::SeqCst)
+    }
+
+    /// Whether at least [`KBD_OUT_BACKPRESSURE_DEPTH`] calls are waiting for the thread. The
+    /// queue itself is unbounded, so nothing is dropped; callers should use this to shed output
+    /// that can be lost, such as mouse movement, until the thread catches up.
+    pub fn is_backpressured(&self) -> bool {
+        self.queue_depth() >= KBD_OUT_BACKPRESSURE_DEPTH
+    }
+
+    /// Hold back the calls made from now on until [`KbdOutSender::flush`], which sends them to the
+    /// thread together. Starting a batch while one is open keeps the calls already collected.
+    pub fn begin_batch(&self) {
+        self.batch.lock().get_or_insert_with(Vec::new);
+    }
+
+    /// Send the calls collected since [`KbdOutSender::begin_batch`] as a single message, and stop
+    /// batching. Does nothing if no batch was started.
+    pub fn flush(&self) -> Result<(), KbdOutError> {
+        match self.batch.lock().take() {
+            Some(commands) if !commands.is_empty() => self.send(KbdOutCommand::Batch(commands)),
+            _ => Ok(()),
+        }
+    }
+
+    fn queue(&self, command: KbdOutCommand) -> Result<(), KbdOutError> {
+        if let Some(batch) = &mut *self.batch.lock() {
+            batch.push(command);
+            return Ok(());
+        }
+        self.send(command)
+    }
+
+    fn send(&self, command: KbdOutCommand) -> Result<(), KbdOutError> {
+        self.depth.fetch_add(1, Ordering::SeqCst);
+        self.tx.send(command).map_err(|_| {
+            self.depth.fetch_sub(1, Ordering::SeqCst);
+            KbdOutError::ChannelClosed
+        })
+    }
+
+    pub fn write_key(&self, key: OsCode, value: KeyValue) -> Result<(), KbdOutError> {
+        self.queue(KbdOutCommand::WriteKey(key, value))
+    }
+
+    pub fn press_key(&self, key: OsCode) -> Result<(), KbdOutError> {
+        self.queue(KbdOutCommand::PressKey(key))
+    }
+
+    pub fn release_key(&self, key: OsCode) -> Result<(), KbdOutError> {
+        self.queue(KbdOutCommand::ReleaseKey(key))
+    }
+
+    pub fn tap_key(&self, key: OsCode) -> Result<(), KbdOutError> {
+        self.queue(KbdOutCommand::TapKey(key))
+    }
+
+    pub fn click_btn(&self, btn: Btn) -> Result<(), KbdOutError> {
+        self.queue(KbdOutCommand::ClickBtn(btn))
+    }
+
+    pub fn release_btn(&self, btn: Btn) -> Result<(), KbdOutError> {
+        self.queue(KbdOutCommand::ReleaseBtn(btn))
+    }
+
+    pub fn scroll(&self, direction: MWheelDirection, distance: u16) -> Result<(), KbdOutError> {
+        self.queue(KbdOutCommand::Scroll(direction, distance))
+    }
+
+    pub fn move_mouse(&self, mv: CalculatedMouseMove) -> Result<(), KbdOutError> {
+        self.queue(KbdOutCommand::MoveMouse(mv))
+    }
+
+    pub fn set_mouse(&self, x: u16, y: u16) -> Result<(), KbdOutError> {
+        self.queue(KbdOutCommand::SetMouse(x, y))
+    }
+
+    pub fn send_unicode(&self, c: char) -> Result<(), KbdOutError> {
+        self.queue(KbdOutCommand::SendUnicode(c))
+    }
+
+    pub fn tap_then_type(&self, trigger: OsCode, text: &str) -> Result<(), KbdOutError> {
+        self.queue(KbdOutCommand::TapThenType(trigger, text.to_owned()))
+    }
+
+    /// Queue a call to [`KbdOut::tick`]. The owner of the handle is responsible for calling this
+    /// once per millisecond if scheduled output or mouse movement is used.
+    pub fn tick(&self) -> Result<(), KbdOutError> {
+        self.queue(KbdOutCommand::Tick)
+    }
+
+    /// Returns a receiver that is signalled once the thread has run every call queued before this
+    /// one and written their output, including relative movement that is waiting for a flush.
+    /// An open batch is flushed first. Output scheduled for later ticks, such as the release of a
+    /// hold, is not waited for.
+    pub fn flush_barrier(&self) -> Result<std::sync::mpsc::Receiver<()>, KbdOutError> {
+        self.flush()?;
+        let (done, rx) = std::sync::mpsc::channel();
+        self.send(KbdOutCommand::Barrier(done))?;
+        Ok(rx)
+    }
+
+    /// Stop the thread once it has run every call queued so far, including an open batch, and
+    /// return its [`KbdOut`].
+    pub fn shutdown(self) -> KbdOut {
+        if self.flush().is_err() {
+            log::error!("KbdOut thread stopped before the last batch was sent");
+        }
+        drop(self.tx);
+        self.worker.join().expect("KbdOut thread should not panic")
+    }
+}
+
+/// Run `command` on the thread of a [`KbdOutSender`], reporting a failure through `errors_tx`.
+fn run_command(
+    kbd_out: &mut KbdOut,
+    command: KbdOutCommand,
+    errors_tx: &std::sync::mpsc::Sender<FailedOutput>,
+) {
+    kbd_out.failed_event = None;
+    let res = match command {
+        KbdOutCommand::WriteKey(key, value) => kbd_out.write_key(key, value),
+        KbdOutCommand::PressKey(key) => kbd_out.press_key(key),
+        KbdOutCommand::ReleaseKey(key) => kbd_out.release_key(key),
+        KbdOutCommand::TapKey(key) => kbd_out.tap_key(key),
+        KbdOutCommand::ClickBtn(btn) => kbd_out.click_btn(btn),
+        KbdOutCommand::ReleaseBtn(btn) => kbd_out.release_btn(btn),
+        KbdOutCommand::Scroll(direction, distance) => kbd_out.scroll(direction, distance),
+        KbdOutCommand::MoveMouse(mv) => kbd_out.move_mouse(mv),
+        KbdOutCommand::SetMouse(x, y) => kbd_out.set_mouse(x, y),
+        KbdOutCommand::SendUnicode(c) => kbd_out.send_unicode(c),
+        KbdOutCommand::TapThenType(trigger, text) => kbd_out.tap_then_type(trigger, &text),
+        KbdOutCommand::Tick => kbd_out.tick(),
+        KbdOutCommand::Barrier(done) => {
+            let res = kbd_out.flush_pending_move(false);
+            // Nobody may be waiting on the barrier any more.
+            let _ = done.send(());
+            res
+        }
+        KbdOutCommand::Batch(commands) => {
+            // Each call reports its own failure, and one failing does not stop the rest.
+            for command in commands {
+                run_command(kbd_out, command, errors_tx);
+            }
+            Ok(())
+        }
+    };
+    if let Err(error) = res {
+        log::error!("failed to send output: {error}");
+        let failed = FailedOutput {
+            event: kbd_out.failed_event.take(),
+            error,
+        };
+        // The handle may already be gone; then nobody is left to poll the error.
+        let _ = errors_tx.send(failed);
+    }
+}
+
+#[cfg(test)]
+mod tests;
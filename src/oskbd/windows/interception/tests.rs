@@ -0,0 +1,2529 @@
+use super::*;
+
+#[test]
+fn unicode_bmp_char_is_single_packet() {
+    let packets: Vec<_> = unicode_packets('é').collect();
+    assert_eq!(packets, vec![(0x00E9, false), (0x00E9, true)]);
+}
+
+#[test]
+fn unicode_astral_char_is_surrogate_pair() {
+    let packets: Vec<_> = unicode_packets('😀').collect();
+    assert_eq!(
+        packets,
+        vec![
+            (0xD83D, false),
+            (0xD83D, true),
+            (0xDE00, false),
+            (0xDE00, true)
+        ]
+    );
+}
+
+#[test]
+fn unicode_string_sends_down_up_per_code_unit() {
+    let s = "a€😀b";
+    let packets: Vec<_> = unicode_string_packets(s).collect();
+    assert_eq!(packets.len(), s.encode_utf16().count() * 2);
+    assert_eq!(packets[0], (u16::from(b'a'), false));
+    assert_eq!(packets[packets.len() - 1], (u16::from(b'b'), true));
+}
+
+#[test]
+fn pixel_coordinates_normalize_to_absolute_range() {
+    assert_eq!(pixel_to_normalized(0, 1920), 0);
+    assert_eq!(pixel_to_normalized(1919, 1920), u16::MAX);
+    assert_eq!(pixel_to_normalized(5000, 1920), u16::MAX);
+    assert_eq!(pixel_to_normalized(540, 1081), u16::MAX / 2);
+    assert_eq!(pixel_to_normalized(100, 0), 0);
+}
+
+#[test]
+fn diagonal_mouse_move_is_single_stroke() {
+    match InputEvent::from_mouse_move_xy(-3, -4).0 {
+        Stroke::Mouse { state, x, y, .. } => {
+            assert_eq!(state, MouseState::MOVE);
+            assert_eq!((x, y), (-3, -4));
+        }
+        stroke => panic!("expected mouse stroke, got {stroke:?}"),
+    }
+    match InputEvent::from_mouse_move(MoveDirection::Up, 7).0 {
+        Stroke::Mouse { x, y, .. } => assert_eq!((x, y), (0, -7)),
+        stroke => panic!("expected mouse stroke, got {stroke:?}"),
+    }
+}
+
+#[test]
+fn hires_scroll_fires_only_on_whole_notches() {
+    let mut acc = ScrollAccumulator::default();
+    assert_eq!(acc.add(MWheelDirection::Up, 40), 0);
+    assert_eq!(acc.add(MWheelDirection::Up, 40), 0);
+    assert_eq!(acc.add(MWheelDirection::Up, 40), 1);
+    // 20 left over from the previous notch
+    assert_eq!(acc.add(MWheelDirection::Up, 79), 0);
+    assert_eq!(acc.add(MWheelDirection::Up, 1), 1);
+    assert_eq!(acc.add(MWheelDirection::Up, 250), 2);
+    // vertical and horizontal are tracked separately
+    assert_eq!(acc.add(MWheelDirection::Left, 90), 0);
+    assert_eq!(acc.add(MWheelDirection::Down, 60), 0);
+    assert_eq!(acc.add(MWheelDirection::Left, 10), -1);
+    assert_eq!(acc.add(MWheelDirection::Down, 90), -1);
+}
+
+#[test]
+fn extra_mouse_buttons_are_unsupported() {
+    for btn in [Btn::Mouse6, Btn::Mouse7] {
+        for is_up in [false, true] {
+            let err = InputEvent::from_mouse_btn(btn, is_up).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+        }
+    }
+    assert!(matches!(
+        InputEvent::from_mouse_btn(Btn::Forward, false).unwrap().0,
+        Stroke::Mouse { state, .. } if state == MouseState::BUTTON_5_DOWN
+    ));
+}
+
+#[test]
+fn extra_mouse_buttons_are_ignored() {
+    let mut kbd_out = KbdOut::new_simulated();
+    for btn in [Btn::Mouse6, Btn::Mouse7] {
+        kbd_out.click_btn(btn).unwrap();
+        kbd_out.release_btn(btn).unwrap();
+        kbd_out.tap_btn(btn, 0).unwrap();
+    }
+    assert!(kbd_out.drain_simulated().is_empty());
+    assert!(kbd_out.held_btns.is_empty());
+    assert!(kbd_out.btn_refreshed.is_empty());
+}
+
+#[test]
+fn consecutive_mouse_moves_are_coalesced() {
+    let mut kbd_out = KbdOut::new_simulated();
+    let ramp = [1, 2, 4, 8];
+    for distance in ramp {
+        kbd_out
+            .move_mouse(CalculatedMouseMove {
+                direction: MoveDirection::Up,
+                distance,
+            })
+            .unwrap();
+        kbd_out
+            .move_mouse(CalculatedMouseMove {
+                direction: MoveDirection::Right,
+                distance,
+            })
+            .unwrap();
+    }
+    kbd_out.move_mouse_xy(-5, 3).unwrap();
+    // 9 move calls, one pending stroke
+    assert_eq!(kbd_out.pending_move, (10, -12));
+}
+
+#[test]
+fn tap_btn_schedules_the_release_after_the_hold() {
+    let mut kbd_out = KbdOut::new_simulated();
+    let btn = |is_up| OutEvent::Stroke(InputEvent::from_mouse_btn(Btn::Left, is_up).unwrap());
+    kbd_out.tap_btn(Btn::Left, 3).unwrap();
+    assert_eq!(kbd_out.drain_simulated(), vec![btn(false)]);
+    kbd_out.tick().unwrap();
+    kbd_out.tick().unwrap();
+    assert!(kbd_out.drain_simulated().is_empty());
+    kbd_out.tick().unwrap();
+    assert_eq!(kbd_out.drain_simulated(), vec![btn(true)]);
+    assert!(kbd_out.held_btns.is_empty());
+}
+
+#[test]
+fn tap_btn_sends_down_then_up() {
+    let mut kbd_out = KbdOut::new_simulated();
+    kbd_out.tap_btn(Btn::Left, 0).unwrap();
+    let written = kbd_out.drain_simulated();
+    assert_eq!(written.len(), 2);
+    assert!(matches!(
+        written[0],
+        OutEvent::Stroke(InputEvent(Stroke::Mouse { state, .. })) if state == MouseState::LEFT_BUTTON_DOWN
+    ));
+    assert!(matches!(
+        written[1],
+        OutEvent::Stroke(InputEvent(Stroke::Mouse { state, .. })) if state == MouseState::LEFT_BUTTON_UP
+    ));
+}
+
+#[test]
+fn inverted_scroll_negates_rolling() {
+    let rolling =
+        |direction, invert| match InputEvent::from_mouse_scroll(direction, 120, invert, 120)
+            .unwrap()
+            .0
+        {
+            Stroke::Mouse { rolling, .. } => rolling,
+            stroke => panic!("expected mouse stroke, got {stroke:?}"),
+        };
+    assert_eq!(rolling(MWheelDirection::Up, false), 120);
+    assert_eq!(rolling(MWheelDirection::Up, true), -120);
+    assert_eq!(rolling(MWheelDirection::Left, false), -120);
+    assert_eq!(rolling(MWheelDirection::Left, true), 120);
+}
+
+#[test]
+fn scroll_is_bounded_by_the_stroke_range() {
+    let mut kbd_out = KbdOut::new_simulated();
+    kbd_out.set_max_scroll_distance(u16::MAX, u16::MAX);
+    let max = i16::MAX as u16;
+    kbd_out.scroll(MWheelDirection::Up, max).unwrap();
+    kbd_out.scroll(MWheelDirection::Down, max).unwrap();
+    kbd_out.set_invert_scroll(true);
+    kbd_out.scroll(MWheelDirection::Down, max).unwrap();
+    let rolling: Vec<_> = kbd_out
+        .drain_simulated()
+        .into_iter()
+        .map(|event| match event {
+            OutEvent::Stroke(InputEvent(Stroke::Mouse { rolling, .. })) => rolling,
+            event => panic!("expected mouse stroke, got {event:?}"),
+        })
+        .collect();
+    assert_eq!(rolling, vec![i16::MAX, -i16::MAX, i16::MAX]);
+
+    let err = kbd_out.scroll(MWheelDirection::Up, max + 1).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    assert!(kbd_out.scroll(MWheelDirection::Left, 40000).is_err());
+    assert!(kbd_out.drain_simulated().is_empty());
+}
+
+#[test]
+fn release_all_held_releases_outstanding_presses() {
+    let mut kbd_out = KbdOut::new_simulated();
+    kbd_out.press_key(OsCode::KEY_A).unwrap();
+    kbd_out.press_key(OsCode::KEY_B).unwrap();
+    kbd_out.release_key(OsCode::KEY_B).unwrap();
+    kbd_out.click_btn(Btn::Right).unwrap();
+    kbd_out.drain_simulated();
+
+    kbd_out.release_all_held().unwrap();
+    let written = kbd_out.drain_simulated();
+    assert_eq!(written.len(), 2);
+    assert!(matches!(
+        written[0],
+        OutEvent::Stroke(InputEvent(Stroke::Keyboard { code: ScanCode::A, state, .. })) if state == KeyState::UP
+    ));
+    assert!(matches!(
+        written[1],
+        OutEvent::Stroke(InputEvent(Stroke::Mouse { state, .. })) if state == MouseState::RIGHT_BUTTON_UP
+    ));
+    assert!(kbd_out.held_keys.is_empty());
+    assert!(kbd_out.held_btns.is_empty());
+}
+
+#[test]
+fn held_keys_reflects_output_state() {
+    let mut kbd_out = KbdOut::new_simulated();
+    kbd_out.press_key(OsCode::KEY_LEFTSHIFT).unwrap();
+    kbd_out.press_key(OsCode::KEY_X).unwrap();
+    kbd_out.write_key(OsCode::KEY_X, KeyValue::Repeat).unwrap();
+    kbd_out.release_key(OsCode::KEY_LEFTSHIFT).unwrap();
+    kbd_out.drain_simulated();
+    assert_eq!(
+        kbd_out.held_keys(),
+        &[OsCode::KEY_X].into_iter().collect::<HashSet<_>>()
+    );
+}
+
+#[test]
+fn simulated_output_records_each_kind() {
+    let mut kbd_out = KbdOut::new_simulated();
+    kbd_out.write_key(OsCode::KEY_A, KeyValue::Press).unwrap();
+    kbd_out.move_mouse_xy(3, -4).unwrap();
+    kbd_out.send_unicode('é').unwrap();
+    kbd_out.write_code(0x41, KeyValue::Release).unwrap();
+    assert_eq!(
+        kbd_out.drain_simulated(),
+        vec![
+            OutEvent::Stroke(InputEvent::from_oscode(OsCode::KEY_A, KeyValue::Press).unwrap()),
+            // The queued move goes out before the first event that is not a move.
+            OutEvent::Stroke(InputEvent::from_mouse_move_xy(3, -4)),
+            OutEvent::Unicode {
+                unit: 0xe9,
+                up: false
+            },
+            OutEvent::Unicode {
+                unit: 0xe9,
+                up: true
+            },
+            OutEvent::VirtualKey {
+                code: 0x41,
+                value: KeyValue::Release
+            },
+        ]
+    );
+}
+
+#[test]
+fn input_event_display() {
+    let display = |event: InputEvent| event.to_string();
+    assert_eq!(
+        display(InputEvent::from_oscode(OsCode::KEY_A, KeyValue::Press).unwrap()),
+        "Key(A, Press)"
+    );
+    assert_eq!(
+        display(InputEvent::from_mouse_btn(Btn::Left, false).unwrap()),
+        "Mouse(Left Down)"
+    );
+    assert_eq!(
+        display(
+            InputEvent::from_mouse_scroll(
+                MWheelDirection::Up,
+                120,
+                false,
+                DEFAULT_MAX_SCROLL_DISTANCE
+            )
+            .unwrap()
+        ),
+        "Scroll(Up 120)"
+    );
+    assert_eq!(
+        display(
+            InputEvent::from_mouse_scroll(
+                MWheelDirection::Left,
+                120,
+                false,
+                DEFAULT_MAX_SCROLL_DISTANCE
+            )
+            .unwrap()
+        ),
+        "Scroll(Left 120)"
+    );
+    assert_eq!(display(InputEvent::from_mouse_move_xy(3, -4)), "Move(3,-4)");
+    assert_ne!(
+        InputEvent::from_mouse_move_xy(3, -4),
+        InputEvent::from_mouse_move_xy(-4, 3)
+    );
+}
+
+#[test]
+fn mouse_accel_ramps_to_max_distance() {
+    let mut kbd_out = KbdOut::new_simulated();
+    kbd_out.set_mouse_accel(AccelRamp {
+        start_distance: 2,
+        max_distance: 12,
+        ticks_to_max: 10,
+    });
+    assert_eq!(
+        kbd_out.move_mouse_accel(MoveDirection::Right, 0).unwrap(),
+        2
+    );
+    assert_eq!(
+        kbd_out.move_mouse_accel(MoveDirection::Right, 5).unwrap(),
+        7
+    );
+    assert_eq!(
+        kbd_out.move_mouse_accel(MoveDirection::Right, 10).unwrap(),
+        12
+    );
+    assert_eq!(
+        kbd_out.move_mouse_accel(MoveDirection::Up, 500).unwrap(),
+        12
+    );
+    assert_eq!(kbd_out.pending_move, (21, -12));
+}
+
+#[test]
+fn raw_stroke_passes_through_unchanged() {
+    let stroke = Stroke::Mouse {
+        state: MouseState::MOVE,
+        flags: MouseFlags::MOVE_NO_COALESCE,
+        rolling: 0,
+        x: 7,
+        y: 9,
+        information: 0x1234,
+    };
+    let mut kbd_out = KbdOut::new_simulated();
+    kbd_out.write_raw_stroke(stroke).unwrap();
+    assert_eq!(
+        kbd_out.drain_simulated(),
+        vec![OutEvent::Stroke(InputEvent(stroke))]
+    );
+}
+
+#[test]
+fn generated_strokes_are_marked_synthetic() {
+    assert!(is_synthetic(
+        &InputEvent::from_oscode(OsCode::KEY_A, KeyValue::Press)
+            .unwrap()
+            .0
+    ));
+    assert!(is_synthetic(
+        &InputEvent::from_mouse_btn(Btn::Left, false).unwrap().0
+    ));
+    assert!(is_synthetic(&InputEvent::from_mouse_move_xy(1, 1).0));
+    let hardware = Stroke::Keyboard {
+        code: ScanCode::A,
+        state: KeyState::DOWN,
+        information: 0,
+    };
+    assert!(!is_synthetic(&hardware));
+}
+
+#[test]
+fn scroll_distance_is_bounded_per_axis() {
+    let mut kbd_out = KbdOut::new_simulated();
+    kbd_out.scroll(MWheelDirection::Up, 30000).unwrap();
+    assert_eq!(
+        kbd_out
+            .scroll(MWheelDirection::Up, 30001)
+            .unwrap_err()
+            .kind(),
+        io::ErrorKind::InvalidInput
+    );
+    kbd_out.set_max_scroll_distance(30000, 600);
+    kbd_out.scroll(MWheelDirection::Right, 600).unwrap();
+    assert!(kbd_out.scroll(MWheelDirection::Right, 601).is_err());
+    kbd_out.scroll(MWheelDirection::Down, 601).unwrap();
+    assert_eq!(kbd_out.drain_simulated().len(), 3);
+}
+
+#[test]
+fn tap_key_sends_down_then_up() {
+    let mut kbd_out = KbdOut::new_simulated();
+    kbd_out.tap_key(OsCode::KEY_Z).unwrap();
+    assert_eq!(
+        kbd_out.drain_simulated(),
+        vec![
+            OutEvent::Stroke(InputEvent::from_oscode(OsCode::KEY_Z, KeyValue::Press).unwrap()),
+            OutEvent::Stroke(InputEvent::from_oscode(OsCode::KEY_Z, KeyValue::Release).unwrap()),
+        ]
+    );
+    assert!(kbd_out.held_keys().is_empty());
+}
+
+#[test]
+fn type_ascii_uses_keys_and_shift() {
+    let mut kbd_out = KbdOut::new_simulated();
+    kbd_out.type_ascii("a?é").unwrap();
+    let key = |code, value| OutEvent::Stroke(InputEvent::from_oscode(code, value).unwrap());
+    assert_eq!(
+        kbd_out.drain_simulated(),
+        vec![
+            key(OsCode::KEY_A, KeyValue::Press),
+            key(OsCode::KEY_A, KeyValue::Release),
+            key(OsCode::KEY_LEFTSHIFT, KeyValue::Press),
+            key(OsCode::KEY_SLASH, KeyValue::Press),
+            key(OsCode::KEY_SLASH, KeyValue::Release),
+            key(OsCode::KEY_LEFTSHIFT, KeyValue::Release),
+            OutEvent::Unicode {
+                unit: 0xe9,
+                up: false
+            },
+            OutEvent::Unicode {
+                unit: 0xe9,
+                up: true
+            },
+        ]
+    );
+}
+
+#[test]
+fn type_ascii_sends_emoji_as_unicode() {
+    let mut kbd_out = KbdOut::new_simulated();
+    kbd_out.type_ascii("B😀").unwrap();
+    let key = |code, value| OutEvent::Stroke(InputEvent::from_oscode(code, value).unwrap());
+    let uc = |unit, up| OutEvent::Unicode { unit, up };
+    assert_eq!(
+        kbd_out.drain_simulated(),
+        vec![
+            key(OsCode::KEY_LEFTSHIFT, KeyValue::Press),
+            key(OsCode::KEY_B, KeyValue::Press),
+            key(OsCode::KEY_B, KeyValue::Release),
+            key(OsCode::KEY_LEFTSHIFT, KeyValue::Release),
+            uc(0xD83D, false),
+            uc(0xD83D, true),
+            uc(0xDE00, false),
+            uc(0xDE00, true),
+        ]
+    );
+}
+
+#[test]
+fn unmapped_oscode_is_an_error() {
+    assert_eq!(
+        InputEvent::from_oscode(OsCode::BTN_LEFT, KeyValue::Press).unwrap_err(),
+        OsCodeMappingError(OsCode::BTN_LEFT)
+    );
+    let mut kbd_out = KbdOut::new_simulated();
+    let err = kbd_out
+        .try_write_key(OsCode::BTN_LEFT, KeyValue::Press)
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    assert!(kbd_out.drain_simulated().is_empty());
+    assert!(kbd_out.held_keys().is_empty());
+}
+
+#[test]
+fn unmapped_oscode_is_dropped_by_press_key() {
+    let mut kbd_out = KbdOut::new_simulated();
+    kbd_out.press_key(OsCode::BTN_LEFT).unwrap();
+    kbd_out.release_key(OsCode::BTN_LEFT).unwrap();
+    assert!(kbd_out.drain_simulated().is_empty());
+    assert!(kbd_out.held_keys().is_empty());
+}
+
+#[test]
+fn passed_through_mouse_inputs_are_dropped() {
+    let mut kbd_out = KbdOut::new_simulated();
+    for key in [
+        OsCode::MouseWheelUp,
+        OsCode::MouseMoveUp,
+        OsCode::MouseMoveDown,
+        OsCode::MouseMoveLeft,
+        OsCode::MouseMoveRight,
+    ] {
+        kbd_out.press_key(key).unwrap();
+        kbd_out.release_key(key).unwrap();
+    }
+    assert!(kbd_out.drain_simulated().is_empty());
+    assert!(kbd_out.held_keys().is_empty());
+}
+
+#[test]
+fn unmapped_oscode_error_names_the_code() {
+    let err = InputEvent::from_oscode(OsCode::BTN_LEFT, KeyValue::Press).unwrap_err();
+    assert!(err.to_string().contains("BTN_LEFT"));
+    let err = KbdOut::new_simulated()
+        .try_write_key(OsCode::BTN_LEFT, KeyValue::Press)
+        .unwrap_err();
+    assert!(err.to_string().contains("BTN_LEFT"));
+}
+
+#[test]
+fn scroll_notch_sends_whole_wheel_deltas() {
+    let mut kbd_out = KbdOut::new_simulated();
+    kbd_out.scroll_notch(MWheelDirection::Down, 3).unwrap();
+    assert!(matches!(
+        kbd_out.drain_simulated()[..],
+        [OutEvent::Stroke(InputEvent(Stroke::Mouse {
+            rolling: -360,
+            ..
+        }))]
+    ));
+    kbd_out.scroll_notch(MWheelDirection::Up, 1).unwrap();
+    assert!(matches!(
+        kbd_out.drain_simulated()[..],
+        [OutEvent::Stroke(InputEvent(Stroke::Mouse { rolling, .. }))] if rolling == WHEEL_DELTA
+    ));
+    assert_eq!(WHEEL_DELTA, 120);
+    assert!(kbd_out.scroll_notch(MWheelDirection::Down, 251).is_err());
+    assert!(kbd_out.scroll_notch(MWheelDirection::Down, 1000).is_err());
+}
+
+#[test]
+fn device_is_used_for_strokes_of_its_kind() {
+    let key = InputEvent::from_oscode(OsCode::KEY_A, KeyValue::Press)
+        .unwrap()
+        .0;
+    let mouse = InputEvent::from_mouse_move_xy(1, 1).0;
+    assert_eq!(target_device(&key, None), 1);
+    assert_eq!(target_device(&mouse, None), 11);
+    assert_eq!(target_device(&key, Some(3)), 3);
+    assert_eq!(target_device(&mouse, Some(3)), 11);
+    assert_eq!(target_device(&mouse, Some(14)), 14);
+    assert_eq!(target_device(&key, Some(14)), 1);
+}
+
+#[test]
+fn mouse_move_stroke_decodes_per_axis() {
+    let hardware_move = InputEvent(Stroke::Mouse {
+        state: MouseState::empty(),
+        flags: MouseFlags::MOVE_RELATIVE,
+        rolling: 0,
+        x: -3,
+        y: 8,
+        information: 0,
+    });
+    assert_eq!(hardware_move.mouse_move(), Some((-3, 8)));
+    let [x, y] = MouseMove::from_axes(-3, 8);
+    assert_eq!(
+        x,
+        Some(MouseMove {
+            direction: MoveDirection::Left,
+            distance: 3
+        })
+    );
+    assert_eq!(
+        y,
+        Some(MouseMove {
+            direction: MoveDirection::Down,
+            distance: 8
+        })
+    );
+    assert_eq!(x.unwrap().oscode(), OsCode::MouseMoveLeft);
+    assert_eq!(y.unwrap().oscode(), OsCode::MouseMoveDown);
+    assert_eq!(
+        MouseMove::from_axes(0, -2),
+        [
+            None,
+            Some(MouseMove {
+                direction: MoveDirection::Up,
+                distance: 2
+            })
+        ]
+    );
+    // Passing on only the horizontal part keeps the rest of the stroke.
+    assert_eq!(
+        hardware_move.with_mouse_move(-3, 0),
+        InputEvent(Stroke::Mouse {
+            state: MouseState::empty(),
+            flags: MouseFlags::MOVE_RELATIVE,
+            rolling: 0,
+            x: -3,
+            y: 0,
+            information: 0,
+        })
+    );
+    assert_eq!(
+        InputEvent::from_mouse_move_xy(-3, 8).mouse_move(),
+        Some((-3, 8))
+    );
+    assert_eq!(InputEvent::from_mouse_set(100, 100).mouse_move(), None);
+    assert_eq!(
+        InputEvent::from_mouse_btn(Btn::Left, false)
+            .unwrap()
+            .mouse_move(),
+        None
+    );
+}
+
+#[test]
+fn smooth_mouse_move_sums_to_total() {
+    let mut kbd_out = KbdOut::new_simulated();
+    kbd_out.move_mouse_smooth(10, -7, 4).unwrap();
+    let moves: Vec<_> = kbd_out
+        .drain_simulated()
+        .into_iter()
+        .map(|event| match event {
+            OutEvent::Stroke(event) => event.mouse_move().unwrap(),
+            event => panic!("expected a mouse move, got {event:?}"),
+        })
+        .collect();
+    assert_eq!(moves.len(), 4);
+    assert_eq!(moves.iter().map(|m| m.0).sum::<i32>(), 10);
+    assert_eq!(moves.iter().map(|m| m.1).sum::<i32>(), -7);
+    assert!(moves.iter().all(|m| (2..=3).contains(&m.0)));
+
+    kbd_out.move_mouse_smooth(10, -7, 1).unwrap();
+    assert_eq!(
+        kbd_out.drain_simulated(),
+        vec![OutEvent::Stroke(InputEvent::from_mouse_move_xy(10, -7))]
+    );
+}
+
+#[test]
+fn repeat_is_a_tagged_down_stroke() {
+    let press = InputEvent::from_oscode(OsCode::KEY_A, KeyValue::Press).unwrap();
+    let repeat = InputEvent::from_oscode(OsCode::KEY_A, KeyValue::Repeat).unwrap();
+    assert!(matches!(
+        repeat.0,
+        Stroke::Keyboard { state, .. } if state == KeyState::DOWN
+    ));
+    assert!(is_synthetic(&repeat.0));
+    assert!(is_repeat(&repeat.0));
+    assert!(!is_repeat(&press.0));
+    assert_ne!(press, repeat);
+    assert_eq!(repeat.to_string(), "Key(A, Repeat)");
+}
+
+#[test]
+fn button_chord_presses_all_before_releasing() {
+    let mut kbd_out = KbdOut::new_simulated();
+    kbd_out.press_btns(&[Btn::Left, Btn::Right]).unwrap();
+    assert_eq!(kbd_out.held_btns.len(), 2);
+    kbd_out.release_btns(&[Btn::Left, Btn::Right]).unwrap();
+    assert!(kbd_out.held_btns.is_empty());
+    let btn = |btn, is_up| OutEvent::Stroke(InputEvent::from_mouse_btn(btn, is_up).unwrap());
+    assert_eq!(
+        kbd_out.drain_simulated(),
+        vec![
+            btn(Btn::Left, false),
+            btn(Btn::Right, false),
+            btn(Btn::Left, true),
+            btn(Btn::Right, true),
+        ]
+    );
+}
+
+#[test]
+fn scroll_accel_ramps_to_max_distance() {
+    let mut kbd_out = KbdOut::new_simulated();
+    assert_eq!(kbd_out.scroll_accel(MWheelDirection::Up, 0).unwrap(), 120);
+    kbd_out.set_scroll_accel(AccelRamp {
+        start_distance: 30,
+        max_distance: 480,
+        ticks_to_max: 9,
+    });
+    let distances: Vec<_> = [0, 1, 3, 9, 100]
+        .into_iter()
+        .map(|tick| kbd_out.scroll_accel(MWheelDirection::Up, tick).unwrap())
+        .collect();
+    assert_eq!(distances, [30, 80, 180, 480, 480]);
+    assert_eq!(kbd_out.drain_simulated().len(), 6);
+}
+
+#[test]
+fn stats_count_sent_events() {
+    let mut kbd_out = KbdOut::new_simulated();
+    kbd_out.tap_key(OsCode::KEY_A).unwrap();
+    kbd_out.write_key(OsCode::KEY_A, KeyValue::Repeat).unwrap();
+    kbd_out.tap_btn(Btn::Left, 0).unwrap();
+    kbd_out.scroll_notch(MWheelDirection::Up, 2).unwrap();
+    kbd_out.scroll(MWheelDirection::Left, 120).unwrap();
+    kbd_out.move_mouse_xy(1, 2).unwrap();
+    kbd_out.move_mouse_xy(3, 4).unwrap();
+    // Not counted until it is flushed.
+    assert_eq!(kbd_out.stats().mouse_moves, 0);
+    kbd_out.flush().unwrap();
+    kbd_out.set_mouse(10, 10).unwrap();
+    assert_eq!(
+        kbd_out.stats(),
+        KbdOutStats {
+            key_presses: 2,
+            key_releases: 1,
+            clicks: 1,
+            scroll_ticks: 2,
+            mouse_moves: 2,
+        }
+    );
+}
+
+#[test]
+fn mouse_rate_limit_bounds_sent_moves() {
+    let mut kbd_out = KbdOut::new_simulated();
+    kbd_out.set_mouse_rate_limit(Some(Duration::from_secs(3600)));
+    for _ in 0..100 {
+        kbd_out.move_mouse_xy(1, -1).unwrap();
+        kbd_out.flush().unwrap();
+    }
+    assert_eq!(
+        kbd_out.drain_simulated(),
+        vec![OutEvent::Stroke(InputEvent::from_mouse_move_xy(1, -1))]
+    );
+    assert_eq!(kbd_out.pending_move, (99, -99));
+    // Other events still go out, after the movement held back by the limit.
+    kbd_out.click_btn(Btn::Left).unwrap();
+    assert_eq!(
+        kbd_out.drain_simulated(),
+        vec![
+            OutEvent::Stroke(InputEvent::from_mouse_move_xy(99, -99)),
+            OutEvent::Stroke(InputEvent::from_mouse_btn(Btn::Left, false).unwrap()),
+        ]
+    );
+}
+
+#[test]
+fn kbd_out_implements_kbd_output() {
+    fn assert_kbd_output<T: crate::oskbd::KbdOutput>() {}
+    assert_kbd_output::<KbdOut>();
+}
+
+#[test]
+fn input_event_bytes_round_trip() {
+    let events = [
+        InputEvent::from_oscode(OsCode::KEY_RIGHTALT, KeyValue::Release).unwrap(),
+        InputEvent::from_mouse_btn(Btn::Backward, false).unwrap(),
+        InputEvent::from_mouse_scroll(MWheelDirection::Left, 240, false, 240).unwrap(),
+        InputEvent::from_mouse_move_xy(-70000, 12),
+        InputEvent::from_mouse_set(65535, 0),
+    ];
+    for event in events {
+        assert_eq!(InputEvent::from_bytes(&event.to_bytes()), Ok(event));
+    }
+    let mut truncated = events[0].to_bytes();
+    truncated.pop();
+    assert_eq!(
+        InputEvent::from_bytes(&truncated),
+        Err(InputEventParseError::Length(8))
+    );
+    assert_eq!(
+        InputEvent::from_bytes(&[7]),
+        Err(InputEventParseError::Kind(7))
+    );
+}
+
+#[test]
+fn move_mouse_to_clamps_to_screen_edges() {
+    let mut kbd_out = KbdOut::new_simulated();
+    kbd_out.set_screen_metrics(ScreenMetrics {
+        left: -1920,
+        top: -200,
+        width: 3840,
+        height: 1281,
+    });
+    let set = |x, y| OutEvent::Stroke(InputEvent::from_mouse_set(x, y));
+    kbd_out.move_mouse_to(-5000, 5000).unwrap();
+    assert_eq!(kbd_out.drain_simulated(), vec![set(0, u16::MAX)]);
+    kbd_out.move_mouse_to(4000, -201).unwrap();
+    assert_eq!(kbd_out.drain_simulated(), vec![set(u16::MAX, 0)]);
+    kbd_out.move_mouse_to(-1920, 440).unwrap();
+    assert_eq!(kbd_out.drain_simulated(), vec![set(0, u16::MAX / 2)]);
+}
+
+#[test]
+fn last_absolute_position_tracks_move_mouse_to() {
+    let mut kbd_out = KbdOut::new_simulated();
+    kbd_out.set_screen_metrics(ScreenMetrics {
+        left: -1920,
+        top: 0,
+        width: 3840,
+        height: 1080,
+    });
+    assert_eq!(kbd_out.last_absolute_position(), None);
+    kbd_out.move_mouse_to(-100, 500).unwrap();
+    assert_eq!(kbd_out.last_absolute_position(), Some((-100, 500)));
+    kbd_out.move_mouse_to(5000, -10).unwrap();
+    assert_eq!(kbd_out.last_absolute_position(), Some((1919, 0)));
+    kbd_out.set_mouse(0, 0).unwrap();
+    assert_eq!(kbd_out.last_absolute_position(), None);
+}
+
+#[test]
+fn dpi_scale_accumulates_fractional_pixels() {
+    let mut kbd_out = KbdOut::new_simulated();
+    kbd_out.set_dpi_scale(1.5);
+    let mut per_tick = vec![];
+    for _ in 0..10 {
+        kbd_out
+            .move_mouse(CalculatedMouseMove {
+                direction: MoveDirection::Right,
+                distance: 1,
+            })
+            .unwrap();
+        per_tick.push(kbd_out.pending_move.0);
+        kbd_out.flush().unwrap();
+    }
+    assert!(per_tick.iter().all(|d| (1..=2).contains(d)));
+    assert_eq!(per_tick.iter().sum::<i32>(), 15);
+    kbd_out.move_mouse_xy(-3, 7).unwrap();
+    assert_eq!(kbd_out.pending_move, (-5, 11));
+}
+
+#[test]
+fn hold_key_releases_after_duration() {
+    let mut kbd_out = KbdOut::new_simulated();
+    kbd_out.hold_key(OsCode::KEY_Q, 5).unwrap();
+    assert_eq!(
+        kbd_out.drain_simulated(),
+        vec![OutEvent::Stroke(
+            InputEvent::from_oscode(OsCode::KEY_Q, KeyValue::Press).unwrap()
+        )]
+    );
+    for _ in 0..4 {
+        kbd_out.tick().unwrap();
+    }
+    assert!(kbd_out.drain_simulated().is_empty());
+    assert!(kbd_out.held_keys().contains(&OsCode::KEY_Q));
+    kbd_out.tick().unwrap();
+    assert_eq!(
+        kbd_out.drain_simulated(),
+        vec![OutEvent::Stroke(
+            InputEvent::from_oscode(OsCode::KEY_Q, KeyValue::Release).unwrap()
+        )]
+    );
+    assert!(kbd_out.held_keys().is_empty());
+}
+
+#[test]
+fn tilt_wheel_decodes_to_horizontal_direction() {
+    let hwheel = |rolling| {
+        InputEvent(Stroke::Mouse {
+            state: MouseState::HWHEEL,
+            flags: MouseFlags::empty(),
+            rolling,
+            x: 0,
+            y: 0,
+            information: 0,
+        })
+    };
+    assert_eq!(
+        hwheel(120).mouse_wheel(),
+        Some((MWheelDirection::Right, 120))
+    );
+    assert_eq!(
+        hwheel(-240).mouse_wheel(),
+        Some((MWheelDirection::Left, 240))
+    );
+    assert_eq!(
+        InputEvent::from_mouse_scroll(MWheelDirection::Down, 120, false, 120)
+            .unwrap()
+            .mouse_wheel(),
+        Some((MWheelDirection::Down, 120))
+    );
+    assert_eq!(InputEvent::from_mouse_move_xy(1, 1).mouse_wheel(), None);
+}
+
+#[test]
+fn click_with_mods_wraps_click_in_modifiers() {
+    let mut kbd_out = KbdOut::new_simulated();
+    kbd_out.press_key(OsCode::KEY_LEFTALT).unwrap();
+    kbd_out.drain_simulated();
+    kbd_out
+        .click_with_mods(
+            Btn::Mid,
+            &[
+                OsCode::KEY_LEFTCTRL,
+                OsCode::KEY_LEFTALT,
+                OsCode::KEY_LEFTSHIFT,
+            ],
+        )
+        .unwrap();
+    let key = |code, value| OutEvent::Stroke(InputEvent::from_oscode(code, value).unwrap());
+    let btn = |btn, is_up| OutEvent::Stroke(InputEvent::from_mouse_btn(btn, is_up).unwrap());
+    assert_eq!(
+        kbd_out.drain_simulated(),
+        vec![
+            key(OsCode::KEY_LEFTCTRL, KeyValue::Press),
+            key(OsCode::KEY_LEFTSHIFT, KeyValue::Press),
+            btn(Btn::Mid, false),
+            btn(Btn::Mid, true),
+            key(OsCode::KEY_LEFTSHIFT, KeyValue::Release),
+            key(OsCode::KEY_LEFTCTRL, KeyValue::Release),
+        ]
+    );
+    assert_eq!(
+        kbd_out.held_keys(),
+        &[OsCode::KEY_LEFTALT].into_iter().collect::<HashSet<_>>()
+    );
+}
+
+#[test]
+fn far_mouse_move_is_chunked() {
+    let mut kbd_out = KbdOut::new_simulated();
+    kbd_out.move_mouse_far(MoveDirection::Left, 100000).unwrap();
+    assert_eq!(
+        kbd_out.drain_simulated(),
+        vec![
+            OutEvent::Stroke(InputEvent::from_mouse_move_xy(-65535, 0)),
+            OutEvent::Stroke(InputEvent::from_mouse_move_xy(-34465, 0)),
+        ]
+    );
+}
+
+#[test]
+fn release_without_press_is_sent_by_default() {
+    // The processing loop releases keys that were held before kanata started.
+    let mut kbd_out = KbdOut::new_simulated();
+    kbd_out.release_key(OsCode::KEY_LEFTSHIFT).unwrap();
+    assert_eq!(
+        kbd_out.drain_simulated(),
+        vec![OutEvent::Stroke(
+            InputEvent::from_oscode(OsCode::KEY_LEFTSHIFT, KeyValue::Release).unwrap()
+        )]
+    );
+}
+
+#[test]
+fn double_release_sends_one_stroke() {
+    let mut kbd_out = KbdOut::new_simulated();
+    kbd_out.set_skip_unheld_releases(true);
+    kbd_out.press_key(OsCode::KEY_K).unwrap();
+    kbd_out.release_key(OsCode::KEY_K).unwrap();
+    kbd_out.release_key(OsCode::KEY_K).unwrap();
+    assert_eq!(kbd_out.drain_simulated().len(), 2);
+
+    kbd_out.set_skip_unheld_releases(false);
+    kbd_out.release_key(OsCode::KEY_K).unwrap();
+    assert_eq!(
+        kbd_out.drain_simulated(),
+        vec![OutEvent::Stroke(
+            InputEvent::from_oscode(OsCode::KEY_K, KeyValue::Release).unwrap()
+        )]
+    );
+}
+
+#[test]
+fn click_on_held_button_is_skipped() {
+    let mut kbd_out = KbdOut::new_simulated();
+    let btn = |is_up| OutEvent::Stroke(InputEvent::from_mouse_btn(Btn::Left, is_up).unwrap());
+    kbd_out.click_btn(Btn::Left).unwrap();
+    kbd_out.click_btn(Btn::Left).unwrap();
+    assert_eq!(kbd_out.drain_simulated(), vec![btn(false)]);
+    kbd_out.release_btn(Btn::Left).unwrap();
+    kbd_out.click_btn(Btn::Left).unwrap();
+    assert_eq!(kbd_out.drain_simulated(), vec![btn(true), btn(false)]);
+
+    kbd_out.set_skip_held_clicks(false);
+    kbd_out.click_btn(Btn::Left).unwrap();
+    assert_eq!(kbd_out.drain_simulated(), vec![btn(false)]);
+}
+
+#[test]
+fn press_and_release_code_track_held_codes() {
+    let mut kbd_out = KbdOut::new_simulated();
+    kbd_out.press_code(0xff).unwrap();
+    assert!(kbd_out.held_codes.contains(&0xff));
+    kbd_out.release_code(0xff).unwrap();
+    assert!(kbd_out.held_codes.is_empty());
+    kbd_out.press_code(0xe8).unwrap();
+    kbd_out.release_all_held().unwrap();
+    let code = |code, value| OutEvent::VirtualKey { code, value };
+    assert_eq!(
+        kbd_out.drain_simulated(),
+        vec![
+            code(0xff, KeyValue::Press),
+            code(0xff, KeyValue::Release),
+            code(0xe8, KeyValue::Press),
+            code(0xe8, KeyValue::Release),
+        ]
+    );
+}
+
+#[test]
+fn scroll_xy_sends_both_wheels() {
+    let mut kbd_out = KbdOut::new_simulated();
+    kbd_out.scroll_xy(-240, 120).unwrap();
+    let wheels: Vec<_> = kbd_out
+        .drain_simulated()
+        .into_iter()
+        .map(|event| match event {
+            OutEvent::Stroke(InputEvent(Stroke::Mouse { state, rolling, .. })) => (state, rolling),
+            other => panic!("expected a mouse stroke, got {other:?}"),
+        })
+        .collect();
+    assert_eq!(
+        wheels,
+        vec![(MouseState::WHEEL, -240), (MouseState::HWHEEL, 120)]
+    );
+}
+
+#[test]
+fn codepoints_are_sent_as_unicode() {
+    let mut kbd_out = KbdOut::new_simulated();
+    let uc = |unit, up| OutEvent::Unicode { unit, up };
+    kbd_out.send_codepoint(0xE9).unwrap();
+    assert_eq!(
+        kbd_out.drain_simulated(),
+        vec![uc(0x00E9, false), uc(0x00E9, true)]
+    );
+    kbd_out.send_codepoint(0x1F600).unwrap();
+    assert_eq!(
+        kbd_out.drain_simulated(),
+        vec![
+            uc(0xD83D, false),
+            uc(0xD83D, true),
+            uc(0xDE00, false),
+            uc(0xDE00, true)
+        ]
+    );
+    for invalid in [0xD800, 0x11_0000] {
+        let err = kbd_out.send_codepoint(invalid).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+    assert!(kbd_out.drain_simulated().is_empty());
+}
+
+#[test]
+fn inertia_decays_until_it_stops() {
+    let mut kbd_out = KbdOut::new_simulated();
+    kbd_out.set_inertia_decay(0.8);
+    kbd_out.start_inertia(MoveDirection::Right, 50);
+    let mut distances = vec![];
+    for _ in 0..100 {
+        kbd_out.tick().unwrap();
+        for event in kbd_out.drain_simulated() {
+            match event {
+                OutEvent::Stroke(e) => distances.push(e.mouse_move().unwrap().0),
+                other => panic!("expected a mouse move, got {other:?}"),
+            }
+        }
+    }
+    assert_eq!(distances[0], 50);
+    assert!(distances.windows(2).all(|w| w[1] <= w[0]));
+    assert!(distances.len() < 30);
+    assert!(!kbd_out.tick_inertia().unwrap());
+}
+
+#[test]
+fn double_click_sends_second_click_after_gap() {
+    let mut kbd_out = KbdOut::new_simulated();
+    kbd_out.double_click(Btn::Left, 50).unwrap();
+    let btn = |is_up| OutEvent::Stroke(InputEvent::from_mouse_btn(Btn::Left, is_up).unwrap());
+    assert_eq!(kbd_out.drain_simulated(), vec![btn(false), btn(true)]);
+    for _ in 0..49 {
+        kbd_out.tick().unwrap();
+    }
+    assert!(kbd_out.drain_simulated().is_empty());
+    kbd_out.tick().unwrap();
+    assert_eq!(kbd_out.drain_simulated(), vec![btn(false), btn(true)]);
+}
+
+#[test]
+fn failed_scheduled_output_does_not_hold_back_the_rest() {
+    let mut kbd_out = KbdOut::new_simulated();
+    kbd_out.hold_key(OsCode::KEY_A, 2).unwrap();
+    kbd_out.hold_key(OsCode::KEY_B, 2).unwrap();
+    kbd_out.set_button_watchdog(Some(Duration::from_millis(2)));
+    kbd_out.click_btn(Btn::Left).unwrap();
+    kbd_out.drain_simulated();
+    kbd_out.fail_simulated_writes = Some(|event| {
+        *event
+            == OutEvent::Stroke(InputEvent::from_oscode(OsCode::KEY_A, KeyValue::Release).unwrap())
+    });
+    kbd_out.tick().unwrap();
+    assert!(kbd_out.tick().is_err());
+    assert_eq!(
+        kbd_out.drain_simulated(),
+        vec![
+            OutEvent::Stroke(InputEvent::from_oscode(OsCode::KEY_B, KeyValue::Release).unwrap()),
+            OutEvent::Stroke(InputEvent::from_mouse_btn(Btn::Left, true).unwrap()),
+        ]
+    );
+    assert!(!kbd_out.has_pending_output());
+}
+
+#[test]
+fn pending_output_clears_once_sent() {
+    let mut kbd_out = KbdOut::new_simulated();
+    assert!(!kbd_out.has_pending_output());
+    kbd_out.hold_key(OsCode::KEY_A, 2).unwrap();
+    kbd_out
+        .move_mouse(CalculatedMouseMove {
+            direction: MoveDirection::Up,
+            distance: 3,
+        })
+        .unwrap();
+    assert!(kbd_out.has_pending_output());
+    kbd_out.tick().unwrap();
+    assert!(kbd_out.has_pending_output());
+    kbd_out.tick().unwrap();
+    assert!(!kbd_out.has_pending_output());
+    assert_eq!(kbd_out.drain_simulated().len(), 3);
+}
+
+#[test]
+fn suspending_releases_held_and_drops_output() {
+    let mut kbd_out = KbdOut::new_simulated();
+    let key = |code, value| OutEvent::Stroke(InputEvent::from_oscode(code, value).unwrap());
+    kbd_out.press_key(OsCode::KEY_A).unwrap();
+    kbd_out.set_suspended(true).unwrap();
+    assert_eq!(
+        kbd_out.drain_simulated(),
+        vec![
+            key(OsCode::KEY_A, KeyValue::Press),
+            key(OsCode::KEY_A, KeyValue::Release)
+        ]
+    );
+    kbd_out.tap_key(OsCode::KEY_B).unwrap();
+    kbd_out.press_key(OsCode::KEY_C).unwrap();
+    kbd_out.click_btn(Btn::Left).unwrap();
+    assert!(kbd_out.drain_simulated().is_empty());
+    kbd_out.set_suspended(false).unwrap();
+    assert!(kbd_out.held_keys().is_empty());
+    kbd_out.tap_key(OsCode::KEY_B).unwrap();
+    assert_eq!(kbd_out.drain_simulated().len(), 2);
+}
+
+#[test]
+fn panic_release_releases_everything_and_suspends() {
+    let mut kbd_out = KbdOut::new_simulated();
+    kbd_out.press_key(OsCode::KEY_LEFTCTRL).unwrap();
+    kbd_out.press_key(OsCode::KEY_A).unwrap();
+    kbd_out.press_code(0xe8).unwrap();
+    kbd_out.click_btn(Btn::Left).unwrap();
+    kbd_out.hold_key(OsCode::KEY_B, 50).unwrap();
+    kbd_out.drain_simulated();
+
+    kbd_out.panic_release().unwrap();
+    let mut sent = kbd_out.drain_simulated();
+    assert_eq!(sent.len(), 5);
+    for event in [
+        OutEvent::Stroke(InputEvent::from_oscode(OsCode::KEY_LEFTCTRL, KeyValue::Release).unwrap()),
+        OutEvent::Stroke(InputEvent::from_oscode(OsCode::KEY_A, KeyValue::Release).unwrap()),
+        OutEvent::Stroke(InputEvent::from_oscode(OsCode::KEY_B, KeyValue::Release).unwrap()),
+        OutEvent::VirtualKey {
+            code: 0xe8,
+            value: KeyValue::Release,
+        },
+        OutEvent::Stroke(InputEvent::from_mouse_btn(Btn::Left, true).unwrap()),
+    ] {
+        assert!(sent.contains(&event), "{event:?} not sent");
+        sent.retain(|e| *e != event);
+    }
+    assert!(kbd_out.held_keys().is_empty());
+    assert!(kbd_out.held_btns.is_empty());
+    assert!(!kbd_out.has_pending_output());
+    // Output stays suspended.
+    kbd_out.tap_key(OsCode::KEY_C).unwrap();
+    assert!(kbd_out.drain_simulated().is_empty());
+    kbd_out.set_suspended(false).unwrap();
+    kbd_out.tap_key(OsCode::KEY_C).unwrap();
+    assert_eq!(kbd_out.drain_simulated().len(), 2);
+}
+
+#[test]
+fn apply_modifiers_sends_only_the_difference() {
+    let mut kbd_out = KbdOut::new_simulated();
+    let key = |code, value| OutEvent::Stroke(InputEvent::from_oscode(code, value).unwrap());
+    kbd_out
+        .apply_modifiers(ModBits::LCTRL | ModBits::LSHIFT)
+        .unwrap();
+    assert_eq!(
+        kbd_out.drain_simulated(),
+        vec![
+            key(OsCode::KEY_LEFTCTRL, KeyValue::Press),
+            key(OsCode::KEY_LEFTSHIFT, KeyValue::Press)
+        ]
+    );
+    kbd_out
+        .apply_modifiers(ModBits::LSHIFT | ModBits::RALT)
+        .unwrap();
+    assert_eq!(
+        kbd_out.drain_simulated(),
+        vec![
+            key(OsCode::KEY_LEFTCTRL, KeyValue::Release),
+            key(OsCode::KEY_RIGHTALT, KeyValue::Press)
+        ]
+    );
+    assert_eq!(kbd_out.held_modifiers(), ModBits::LSHIFT | ModBits::RALT);
+    kbd_out
+        .apply_modifiers(ModBits::LSHIFT | ModBits::RALT)
+        .unwrap();
+    assert!(kbd_out.drain_simulated().is_empty());
+    kbd_out.apply_modifiers(ModBits::NONE).unwrap();
+    assert_eq!(kbd_out.drain_simulated().len(), 2);
+}
+
+#[test]
+fn paste_text_sends_ctrl_v_and_restores_clipboard() {
+    let mut kbd_out = KbdOut::new_simulated();
+    kbd_out.simulated_clipboard = Some("old".to_owned());
+    kbd_out.paste_text("hello").unwrap();
+    assert_eq!(kbd_out.simulated_clipboard.as_deref(), Some("hello"));
+    let key = |code, value| OutEvent::Stroke(InputEvent::from_oscode(code, value).unwrap());
+    assert_eq!(
+        kbd_out.drain_simulated(),
+        vec![
+            key(OsCode::KEY_LEFTCTRL, KeyValue::Press),
+            key(OsCode::KEY_V, KeyValue::Press),
+            key(OsCode::KEY_V, KeyValue::Release),
+            key(OsCode::KEY_LEFTCTRL, KeyValue::Release),
+        ]
+    );
+    kbd_out.paste_text("again").unwrap();
+    for _ in 0..CLIPBOARD_RESTORE_DELAY_MS {
+        kbd_out.tick().unwrap();
+    }
+    assert_eq!(kbd_out.simulated_clipboard.as_deref(), Some("old"));
+
+    kbd_out.set_paste_preserves_clipboard(false);
+    kbd_out.paste_text("kept").unwrap();
+    for _ in 0..CLIPBOARD_RESTORE_DELAY_MS {
+        kbd_out.tick().unwrap();
+    }
+    assert_eq!(kbd_out.simulated_clipboard.as_deref(), Some("kept"));
+}
+
+#[test]
+fn mouse_buttons_round_trip() {
+    for btn in [Btn::Left, Btn::Right, Btn::Mid, Btn::Backward, Btn::Forward] {
+        for is_up in [false, true] {
+            let event = InputEvent::from_mouse_btn(btn, is_up).unwrap();
+            assert_eq!(event.mouse_btn(), Some((btn, is_up)));
+        }
+    }
+    let scroll = InputEvent::from_mouse_scroll(MWheelDirection::Up, 120, false, 120).unwrap();
+    assert_eq!(scroll.mouse_btn(), None);
+    let key = InputEvent::from_oscode(OsCode::KEY_A, KeyValue::Press).unwrap();
+    assert_eq!(key.mouse_btn(), None);
+}
+
+#[test]
+fn tap_gap_delays_the_release() {
+    let mut kbd_out = KbdOut::new_simulated();
+    kbd_out.set_tap_gap(3);
+    kbd_out.tap_key(OsCode::KEY_X).unwrap();
+    kbd_out.tap_btn(Btn::Left, 0).unwrap();
+    let key = |value| OutEvent::Stroke(InputEvent::from_oscode(OsCode::KEY_X, value).unwrap());
+    let btn = |is_up| OutEvent::Stroke(InputEvent::from_mouse_btn(Btn::Left, is_up).unwrap());
+    assert_eq!(
+        kbd_out.drain_simulated(),
+        vec![key(KeyValue::Press), btn(false)]
+    );
+    kbd_out.tick().unwrap();
+    kbd_out.tick().unwrap();
+    assert!(kbd_out.drain_simulated().is_empty());
+    kbd_out.tick().unwrap();
+    assert_eq!(
+        kbd_out.drain_simulated(),
+        vec![key(KeyValue::Release), btn(true)]
+    );
+}
+
+#[test]
+fn scroll_pixels_sends_unquantized_rolling() {
+    let mut kbd_out = KbdOut::new_simulated();
+    kbd_out.scroll_pixels(MWheelDirection::Down, 37).unwrap();
+    kbd_out.scroll_pixels(MWheelDirection::Up, -5).unwrap();
+    kbd_out.scroll_pixels(MWheelDirection::Right, 1).unwrap();
+    kbd_out.scroll_pixels(MWheelDirection::Left, 0).unwrap();
+    let wheels: Vec<_> = kbd_out
+        .drain_simulated()
+        .into_iter()
+        .map(|event| match event {
+            OutEvent::Stroke(InputEvent(Stroke::Mouse { state, rolling, .. })) => (state, rolling),
+            other => panic!("expected a mouse stroke, got {other:?}"),
+        })
+        .collect();
+    assert_eq!(
+        wheels,
+        vec![
+            (MouseState::WHEEL, -37),
+            (MouseState::WHEEL, -5),
+            (MouseState::HWHEEL, 1)
+        ]
+    );
+}
+
+#[test]
+fn sender_runs_calls_on_its_thread_in_order() {
+    let sender = KbdOutSender::spawn(KbdOut::new_simulated());
+    sender.tap_key(OsCode::KEY_K).unwrap();
+    sender.click_btn(Btn::Right).unwrap();
+    sender.release_btn(Btn::Right).unwrap();
+    sender.send_unicode('é').unwrap();
+    let mut kbd_out = sender.shutdown();
+    let key = |value| OutEvent::Stroke(InputEvent::from_oscode(OsCode::KEY_K, value).unwrap());
+    let btn = |is_up| OutEvent::Stroke(InputEvent::from_mouse_btn(Btn::Right, is_up).unwrap());
+    assert_eq!(
+        kbd_out.drain_simulated(),
+        vec![
+            key(KeyValue::Press),
+            key(KeyValue::Release),
+            btn(false),
+            btn(true),
+            OutEvent::Unicode {
+                unit: 0x00E9,
+                up: false
+            },
+            OutEvent::Unicode {
+                unit: 0x00E9,
+                up: true
+            },
+        ]
+    );
+}
+
+#[test]
+fn pre_send_hook_can_drop_strokes() {
+    let mut kbd_out = KbdOut::new_simulated();
+    kbd_out.set_pre_send_hook(Some(Box::new(|event: &InputEvent| {
+        event.mouse_wheel().is_none()
+    })));
+    kbd_out.scroll(MWheelDirection::Up, 120).unwrap();
+    kbd_out.scroll_xy(120, -120).unwrap();
+    kbd_out.tap_key(OsCode::KEY_A).unwrap();
+    assert_eq!(kbd_out.drain_simulated().len(), 2);
+    assert_eq!(kbd_out.stats().scroll_ticks, 0);
+    kbd_out.set_pre_send_hook(None);
+    kbd_out.scroll(MWheelDirection::Up, 120).unwrap();
+    assert_eq!(kbd_out.drain_simulated().len(), 1);
+}
+
+#[test]
+fn accumulated_scroll_adds_up_small_distances() {
+    let mut kbd_out = KbdOut::new_simulated();
+    kbd_out.set_scroll_accumulation(true);
+    for _ in 0..10 {
+        kbd_out.scroll(MWheelDirection::Down, 30).unwrap();
+    }
+    let total = |events: Vec<OutEvent>| -> i32 {
+        events
+            .into_iter()
+            .map(|event| match event {
+                OutEvent::Stroke(InputEvent(Stroke::Mouse { rolling, .. })) => i32::from(rolling),
+                other => panic!("expected a mouse stroke, got {other:?}"),
+            })
+            .sum()
+    };
+    // 300 scrolled, of which two whole notches are sent and 60 is carried over.
+    assert_eq!(total(kbd_out.drain_simulated()), -240);
+    kbd_out.scroll(MWheelDirection::Down, 60).unwrap();
+    assert_eq!(total(kbd_out.drain_simulated()), -120);
+    kbd_out.scroll(MWheelDirection::Down, 30).unwrap();
+    kbd_out.scroll(MWheelDirection::Up, 30).unwrap();
+    assert!(kbd_out.drain_simulated().is_empty());
+}
+
+#[test]
+fn press_key_named_parses_config_names() {
+    let mut kbd_out = KbdOut::new_simulated();
+    kbd_out.press_key_named("lsft").unwrap();
+    assert_eq!(
+        kbd_out.drain_simulated(),
+        vec![OutEvent::Stroke(
+            InputEvent::from_oscode(OsCode::KEY_LEFTSHIFT, KeyValue::Press).unwrap()
+        )]
+    );
+    let err = kbd_out.press_key_named("notakey").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    assert!(kbd_out.drain_simulated().is_empty());
+}
+
+#[test]
+fn swapped_nav_buttons_send_the_other_state() {
+    let mut kbd_out = KbdOut::new_simulated();
+    kbd_out.set_swap_nav_buttons(true);
+    kbd_out.tap_btn(Btn::Forward, 0).unwrap();
+    kbd_out.tap_btn(Btn::Backward, 0).unwrap();
+    kbd_out.tap_btn(Btn::Left, 0).unwrap();
+    let states: Vec<_> = kbd_out
+        .drain_simulated()
+        .into_iter()
+        .map(|event| match event {
+            OutEvent::Stroke(InputEvent(Stroke::Mouse { state, .. })) => state,
+            other => panic!("expected a mouse stroke, got {other:?}"),
+        })
+        .collect();
+    assert_eq!(
+        states,
+        vec![
+            MouseState::BUTTON_4_DOWN,
+            MouseState::BUTTON_4_UP,
+            MouseState::BUTTON_5_DOWN,
+            MouseState::BUTTON_5_UP,
+            MouseState::LEFT_BUTTON_DOWN,
+            MouseState::LEFT_BUTTON_UP,
+        ]
+    );
+    assert!(kbd_out.held_btns.is_empty());
+}
+
+#[test]
+fn tap_chord_releases_in_reverse_order() {
+    let mut kbd_out = KbdOut::new_simulated();
+    let key = |code, value| OutEvent::Stroke(InputEvent::from_oscode(code, value).unwrap());
+    let chord = [OsCode::KEY_LEFTCTRL, OsCode::KEY_LEFTSHIFT, OsCode::KEY_ESC];
+    kbd_out.tap_chord(&chord).unwrap();
+    assert_eq!(
+        kbd_out.drain_simulated(),
+        vec![
+            key(OsCode::KEY_LEFTCTRL, KeyValue::Press),
+            key(OsCode::KEY_LEFTSHIFT, KeyValue::Press),
+            key(OsCode::KEY_ESC, KeyValue::Press),
+            key(OsCode::KEY_ESC, KeyValue::Release),
+            key(OsCode::KEY_LEFTSHIFT, KeyValue::Release),
+            key(OsCode::KEY_LEFTCTRL, KeyValue::Release),
+        ]
+    );
+    kbd_out.press_key(OsCode::KEY_LEFTCTRL).unwrap();
+    kbd_out.drain_simulated();
+    kbd_out.tap_chord(&chord).unwrap();
+    assert_eq!(kbd_out.drain_simulated().len(), 4);
+    assert!(kbd_out.held_keys().contains(&OsCode::KEY_LEFTCTRL));
+}
+
+#[test]
+fn sender_reports_failed_calls() {
+    let sender = KbdOutSender::spawn(KbdOut::new_simulated());
+    sender.scroll(MWheelDirection::Up, u16::MAX).unwrap();
+    sender.tap_key(OsCode::KEY_A).unwrap();
+    // Wait for the thread to run both calls.
+    let start = Instant::now();
+    let mut errors = vec![];
+    while errors.is_empty() && start.elapsed() < Duration::from_secs(5) {
+        errors.extend(sender.poll_errors());
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0].error.to_string().contains("maximum"));
+    assert_eq!(errors[0].event, None);
+    assert_eq!(sender.shutdown().drain_simulated().len(), 2);
+}
+
+#[test]
+fn sender_reports_the_event_of_a_failed_write() {
+    let mut kbd_out = KbdOut::new_simulated();
+    kbd_out.fail_simulated_writes = Some(|_| true);
+    let sender = KbdOutSender::spawn(kbd_out);
+    sender.tap_key(OsCode::KEY_A).unwrap();
+    sender.click_btn(Btn::Left).unwrap();
+    let start = Instant::now();
+    let mut errors = vec![];
+    while errors.len() < 2 && start.elapsed() < Duration::from_secs(5) {
+        errors.extend(sender.poll_errors());
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    let events: Vec<_> = errors.iter().map(|failed| failed.event).collect();
+    assert_eq!(
+        events,
+        vec![
+            Some(OutEvent::Stroke(
+                InputEvent::from_oscode(OsCode::KEY_A, KeyValue::Press).unwrap()
+            )),
+            Some(OutEvent::Stroke(
+                InputEvent::from_mouse_btn(Btn::Left, false).unwrap()
+            )),
+        ]
+    );
+    assert!(errors
+        .iter()
+        .all(|failed| failed.error.kind() == io::ErrorKind::BrokenPipe));
+}
+
+#[test]
+fn move_step_sends_whole_steps() {
+    let mut kbd_out = KbdOut::new_simulated();
+    kbd_out.set_move_step(5);
+    let mut sent = vec![];
+    for distance in [2, 2, 2, 7, 1] {
+        kbd_out
+            .move_mouse(CalculatedMouseMove {
+                direction: MoveDirection::Left,
+                distance,
+            })
+            .unwrap();
+        kbd_out.tick().unwrap();
+        sent.extend(
+            kbd_out
+                .drain_simulated()
+                .into_iter()
+                .map(|event| match event {
+                    OutEvent::Stroke(e) => e.mouse_move().unwrap().0,
+                    other => panic!("expected a mouse move, got {other:?}"),
+                }),
+        );
+    }
+    // 14 pixels moved in total, with 4 carried over.
+    assert_eq!(sent, vec![-5, -5]);
+}
+
+#[test]
+fn reset_releases_held_and_clears_accumulators() {
+    let mut kbd_out = KbdOut::new_simulated();
+    let key = |code, value| OutEvent::Stroke(InputEvent::from_oscode(code, value).unwrap());
+    kbd_out.press_key(OsCode::KEY_LEFTALT).unwrap();
+    kbd_out.hold_key(OsCode::KEY_TAB, 100).unwrap();
+    kbd_out.scroll_hires(MWheelDirection::Up, 90).unwrap();
+    kbd_out.set_scroll_accumulation(true);
+    kbd_out.scroll(MWheelDirection::Right, 100).unwrap();
+    kbd_out.start_inertia(MoveDirection::Up, 10);
+    kbd_out.drain_simulated();
+    kbd_out.reset().unwrap();
+    let mut released = kbd_out.drain_simulated();
+    released.sort_by_key(|event| format!("{event:?}"));
+    let mut expected = vec![
+        key(OsCode::KEY_LEFTALT, KeyValue::Release),
+        key(OsCode::KEY_TAB, KeyValue::Release),
+    ];
+    expected.sort_by_key(|event| format!("{event:?}"));
+    assert_eq!(released, expected);
+    assert_eq!(kbd_out.held_modifiers(), ModBits::NONE);
+    assert!(!kbd_out.has_pending_output());
+    // Nothing carries over from before the reset.
+    kbd_out.scroll_hires(MWheelDirection::Up, 10).unwrap();
+    kbd_out.scroll(MWheelDirection::Right, 20).unwrap();
+    for _ in 0..100 {
+        kbd_out.tick().unwrap();
+    }
+    assert!(kbd_out.drain_simulated().is_empty());
+}
+
+#[test]
+fn scroll_page_sends_page_sized_rolling() {
+    let mut kbd_out = KbdOut::new_simulated();
+    let page_delta = i32::from(PAGE_SCROLL_NOTCHES * HI_RES_SCROLL_UNITS_IN_LO_RES);
+    let rolling = |events: Vec<OutEvent>| -> Vec<i32> {
+        events
+            .into_iter()
+            .map(|event| match event {
+                OutEvent::Stroke(InputEvent(Stroke::Mouse { rolling, .. })) => i32::from(rolling),
+                other => panic!("expected a mouse stroke, got {other:?}"),
+            })
+            .collect()
+    };
+    kbd_out.scroll_page(MWheelDirection::Down, 2).unwrap();
+    assert_eq!(rolling(kbd_out.drain_simulated()), vec![-2 * page_delta]);
+    // 30 pages are above the scroll distance bound and are split.
+    kbd_out.scroll_page(MWheelDirection::Up, 30).unwrap();
+    let sent = rolling(kbd_out.drain_simulated());
+    assert!(sent.len() > 1);
+    assert_eq!(sent.iter().sum::<i32>(), 30 * page_delta);
+}
+
+#[test]
+fn distance_math_saturates_at_boundaries() {
+    let max = DEFAULT_MAX_SCROLL_DISTANCE;
+    let notches_in_max = max / HI_RES_SCROLL_UNITS_IN_LO_RES;
+    let mut kbd_out = KbdOut::new_simulated();
+    kbd_out
+        .scroll_notch(MWheelDirection::Up, notches_in_max)
+        .unwrap();
+    assert!(kbd_out
+        .scroll_notch(MWheelDirection::Up, notches_in_max + 1)
+        .is_err());
+    assert!(kbd_out.scroll_notch(MWheelDirection::Up, u16::MAX).is_err());
+    assert_eq!(kbd_out.drain_simulated().len(), 1);
+
+    kbd_out
+        .scroll_page(MWheelDirection::Down, u16::MAX)
+        .unwrap();
+    let sent = kbd_out.drain_simulated();
+    let total_notches = u32::from(u16::MAX) * u32::from(PAGE_SCROLL_NOTCHES);
+    assert_eq!(
+        sent.len() as u32,
+        total_notches.div_ceil(u32::from(notches_in_max))
+    );
+    assert!(sent.iter().all(|event| matches!(
+        event,
+        OutEvent::Stroke(InputEvent(Stroke::Mouse { rolling, .. }))
+            if rolling.unsigned_abs() <= max
+    )));
+
+    assert_eq!(
+        bounded_scroll_distance(MWheelDirection::Up, u64::MAX, u16::MAX)
+            .unwrap_err()
+            .kind(),
+        io::ErrorKind::InvalidInput
+    );
+
+    let mut acc = ScrollAccumulator {
+        vertical: i64::MAX,
+        horizontal: i64::MIN,
+    };
+    assert_eq!(acc.add(MWheelDirection::Up, u32::MAX), i64::MAX / 100);
+    assert_eq!(acc.add(MWheelDirection::Left, u32::MAX), i64::MIN / 100);
+
+    // The moves saturate, and what was cut off is not carried over as a rounding error.
+    kbd_out.set_dpi_scale(f32::MAX);
+    let right = CalculatedMouseMove {
+        direction: MoveDirection::Right,
+        distance: u16::MAX,
+    };
+    kbd_out.move_mouse(right).unwrap();
+    kbd_out.move_mouse(right).unwrap();
+    assert_eq!(kbd_out.pending_move, (i32::MAX, 0));
+    assert!(kbd_out.dpi_remainder.0.abs() <= 0.5);
+}
+
+#[test]
+fn tap_combo_parses_modifier_prefixes() {
+    let mut kbd_out = KbdOut::new_simulated();
+    let key = |code, value| OutEvent::Stroke(InputEvent::from_oscode(code, value).unwrap());
+    kbd_out.tap_combo("C-c").unwrap();
+    assert_eq!(
+        kbd_out.drain_simulated(),
+        vec![
+            key(OsCode::KEY_LEFTCTRL, KeyValue::Press),
+            key(OsCode::KEY_C, KeyValue::Press),
+            key(OsCode::KEY_C, KeyValue::Release),
+            key(OsCode::KEY_LEFTCTRL, KeyValue::Release),
+        ]
+    );
+    kbd_out.tap_combo("C-S-a").unwrap();
+    assert_eq!(
+        kbd_out.drain_simulated(),
+        vec![
+            key(OsCode::KEY_LEFTCTRL, KeyValue::Press),
+            key(OsCode::KEY_LEFTSHIFT, KeyValue::Press),
+            key(OsCode::KEY_A, KeyValue::Press),
+            key(OsCode::KEY_A, KeyValue::Release),
+            key(OsCode::KEY_LEFTSHIFT, KeyValue::Release),
+            key(OsCode::KEY_LEFTCTRL, KeyValue::Release),
+        ]
+    );
+    for invalid in ["C-C-a", "C-", "X-a"] {
+        let err = kbd_out.tap_combo(invalid).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+    assert!(kbd_out.drain_simulated().is_empty());
+}
+
+#[test]
+fn strokes_carry_the_source_id() {
+    let mut kbd_out = KbdOut::new_simulated();
+    kbd_out.set_source_id(0x2a);
+    kbd_out.press_key(OsCode::KEY_A).unwrap();
+    kbd_out.write_key(OsCode::KEY_A, KeyValue::Repeat).unwrap();
+    kbd_out.click_btn(Btn::Left).unwrap();
+    let hardware = Stroke::Keyboard {
+        code: ScanCode::A,
+        state: KeyState::UP,
+        information: 0,
+    };
+    kbd_out.write_raw_stroke(hardware).unwrap();
+    let strokes: Vec<_> = kbd_out
+        .drain_simulated()
+        .into_iter()
+        .map(|event| match event {
+            OutEvent::Stroke(InputEvent(stroke)) => stroke,
+            other => panic!("expected a stroke, got {other:?}"),
+        })
+        .collect();
+    for stroke in &strokes[..3] {
+        assert!(is_synthetic(stroke));
+        assert_eq!(source_id(stroke), Some(0x2a));
+    }
+    assert!(is_repeat(&strokes[1]));
+    assert_eq!(source_id(&strokes[3]), None);
+}
+
+#[test]
+fn polar_move_at_cardinal_angles() {
+    let mut kbd_out = KbdOut::new_simulated();
+    for angle in [0.0, 90.0, 180.0, 270.0, -90.0, 450.0] {
+        kbd_out.move_mouse_polar(angle, 10).unwrap();
+        kbd_out.flush().unwrap();
+    }
+    assert_eq!(
+        kbd_out.drain_simulated(),
+        [(10, 0), (0, -10), (-10, 0), (0, 10), (0, 10), (0, -10)]
+            .map(|(dx, dy)| OutEvent::Stroke(InputEvent::from_mouse_move_xy(dx, dy)))
+    );
+}
+
+#[test]
+fn polar_move_carries_fractions() {
+    let mut kbd_out = KbdOut::new_simulated();
+    // cos(60°) * 1 = 0.5 pixels to the right per move.
+    for _ in 0..4 {
+        kbd_out.move_mouse_polar(60.0, 1).unwrap();
+    }
+    kbd_out.flush().unwrap();
+    let moved = kbd_out
+        .drain_simulated()
+        .iter()
+        .filter_map(|event| match event {
+            OutEvent::Stroke(stroke) => stroke.mouse_move(),
+            _ => None,
+        })
+        .fold((0, 0), |(x, y), (dx, dy)| (x + dx, y + dy));
+    assert_eq!(moved, (2, -3));
+}
+
+#[test]
+fn cancel_scheduled_drops_delayed_release() {
+    let mut kbd_out = KbdOut::new_simulated();
+    kbd_out.hold_key(OsCode::KEY_Q, 5).unwrap();
+    kbd_out.start_inertia(MoveDirection::Right, 10);
+    kbd_out.drain_simulated();
+    kbd_out.cancel_scheduled();
+    assert!(!kbd_out.has_pending_output());
+    for _ in 0..10 {
+        kbd_out.tick().unwrap();
+    }
+    assert!(kbd_out.drain_simulated().is_empty());
+    assert!(kbd_out.held_keys().contains(&OsCode::KEY_Q));
+}
+
+#[test]
+fn cancel_scheduled_with_release_sends_delayed_release() {
+    let mut kbd_out = KbdOut::new_simulated();
+    kbd_out.hold_key(OsCode::KEY_Q, 5).unwrap();
+    kbd_out.hold_key(OsCode::KEY_W, 2).unwrap();
+    kbd_out.start_inertia(MoveDirection::Right, 10);
+    kbd_out.drain_simulated();
+    kbd_out.cancel_scheduled_with_release().unwrap();
+    let release =
+        |code| OutEvent::Stroke(InputEvent::from_oscode(code, KeyValue::Release).unwrap());
+    assert_eq!(
+        kbd_out.drain_simulated(),
+        vec![release(OsCode::KEY_W), release(OsCode::KEY_Q)]
+    );
+    assert!(!kbd_out.has_pending_output());
+    assert!(kbd_out.held_keys().is_empty());
+    for _ in 0..10 {
+        kbd_out.tick().unwrap();
+    }
+    assert!(kbd_out.drain_simulated().is_empty());
+}
+
+#[test]
+fn sender_reports_backpressure() {
+    let mut kbd_out = KbdOut::new_simulated();
+    // Hold the thread in the first stroke until the gate is dropped.
+    let (gate, wait) = std::sync::mpsc::channel::<()>();
+    kbd_out.set_pre_send_hook(Some(Box::new(move |_| {
+        let _ = wait.recv();
+        true
+    })));
+    let sender = KbdOutSender::spawn(kbd_out);
+    assert!(!sender.is_backpressured());
+    for _ in 0..KBD_OUT_BACKPRESSURE_DEPTH - 1 {
+        sender.tap_key(OsCode::KEY_A).unwrap();
+    }
+    assert!(!sender.is_backpressured());
+    sender.tap_key(OsCode::KEY_A).unwrap();
+    assert!(sender.is_backpressured());
+    drop(gate);
+    let start = Instant::now();
+    while sender.queue_depth() > 0 && start.elapsed() < Duration::from_secs(5) {
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    assert!(!sender.is_backpressured());
+    assert_eq!(
+        sender.shutdown().drain_simulated().len(),
+        2 * KBD_OUT_BACKPRESSURE_DEPTH
+    );
+}
+
+#[test]
+fn flush_barrier_fires_after_prior_output_is_written() {
+    let (tx, rx) = std::sync::mpsc::channel();
+    let mut kbd_out = KbdOut::new_with_channel(tx);
+    // Hold the thread in the first stroke until the gate is dropped.
+    let (gate, wait) = std::sync::mpsc::channel::<()>();
+    kbd_out.set_pre_send_hook(Some(Box::new(move |_| {
+        let _ = wait.recv();
+        true
+    })));
+    let sender = KbdOutSender::spawn(kbd_out);
+    sender.tap_key(OsCode::KEY_A).unwrap();
+    sender.tap_key(OsCode::KEY_B).unwrap();
+    let barrier = sender.flush_barrier().unwrap();
+    assert_eq!(
+        barrier.try_recv(),
+        Err(std::sync::mpsc::TryRecvError::Empty)
+    );
+    drop(gate);
+    barrier.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(rx.try_iter().count(), 4);
+    drop(sender.shutdown());
+}
+
+#[test]
+fn sender_batch_is_sent_as_one_message() {
+    let (tx, rx) = std::sync::mpsc::channel();
+    let mut kbd_out = KbdOut::new_with_channel(tx);
+    // Hold the thread in the first stroke until the gate is dropped.
+    let (gate, wait) = std::sync::mpsc::channel::<()>();
+    kbd_out.set_pre_send_hook(Some(Box::new(move |_| {
+        let _ = wait.recv();
+        true
+    })));
+    let sender = KbdOutSender::spawn(kbd_out);
+    sender.begin_batch();
+    for key in [OsCode::KEY_H, OsCode::KEY_I, OsCode::KEY_ENTER] {
+        sender.tap_key(key).unwrap();
+    }
+    assert_eq!(sender.queue_depth(), 0);
+    sender.flush().unwrap();
+    assert_eq!(sender.queue_depth(), 1);
+    // Calls after the flush are no longer batched.
+    sender.tap_key(OsCode::KEY_A).unwrap();
+    assert_eq!(sender.queue_depth(), 2);
+    drop(gate);
+    let barrier = sender.flush_barrier().unwrap();
+    barrier.recv_timeout(Duration::from_secs(5)).unwrap();
+    let key = |key, value| OutEvent::Stroke(InputEvent::from_oscode(key, value).unwrap());
+    let expected: Vec<_> = [
+        OsCode::KEY_H,
+        OsCode::KEY_I,
+        OsCode::KEY_ENTER,
+        OsCode::KEY_A,
+    ]
+    .into_iter()
+    .flat_map(|k| [key(k, KeyValue::Press), key(k, KeyValue::Release)])
+    .collect();
+    assert_eq!(rx.try_iter().collect::<Vec<_>>(), expected);
+    drop(sender.shutdown());
+}
+
+#[test]
+fn sender_shutdown_sends_an_open_batch() {
+    let sender = KbdOutSender::spawn(KbdOut::new_simulated());
+    sender.begin_batch();
+    sender.tap_key(OsCode::KEY_A).unwrap();
+    assert_eq!(sender.shutdown().drain_simulated().len(), 2);
+}
+
+#[test]
+fn extended_scancode_sets_e0() {
+    let mut kbd_out = KbdOut::new_simulated();
+    // Right control is left control with the E0 prefix.
+    kbd_out
+        .write_extended_scancode(0x1d, true, KeyValue::Press)
+        .unwrap();
+    kbd_out
+        .write_extended_scancode(0x1d, false, KeyValue::Release)
+        .unwrap();
+    assert_eq!(
+        kbd_out.drain_simulated(),
+        vec![
+            OutEvent::Stroke(
+                InputEvent::from_oscode(OsCode::KEY_RIGHTCTRL, KeyValue::Press).unwrap()
+            ),
+            OutEvent::Stroke(
+                InputEvent::from_oscode(OsCode::KEY_LEFTCTRL, KeyValue::Release).unwrap()
+            ),
+        ]
+    );
+    assert!(kbd_out
+        .write_extended_scancode(0x1d, false, KeyValue::Tap)
+        .is_err());
+}
+
+#[test]
+fn raw_scancode_is_sent_as_is() {
+    let mut kbd_out = KbdOut::new_simulated();
+    kbd_out.write_raw_scancode(0x62, KeyValue::Press).unwrap();
+    kbd_out.write_raw_scancode(0x62, KeyValue::Release).unwrap();
+    let stroke = |state| {
+        OutEvent::Stroke(InputEvent(Stroke::Keyboard {
+            code: ScanCode::Zoom,
+            state,
+            information: KANATA_SYNTHETIC_MARK,
+        }))
+    };
+    assert_eq!(
+        kbd_out.drain_simulated(),
+        vec![stroke(KeyState::DOWN), stroke(KeyState::UP)]
+    );
+    // 0x55 is not a scancode of the interception driver.
+    assert!(matches!(
+        kbd_out.write_raw_scancode(0x55, KeyValue::Press),
+        Err(KbdOutError::InvalidInput(_))
+    ));
+    assert!(kbd_out.drain_simulated().is_empty());
+    assert!(kbd_out.held_codes.is_empty());
+}
+
+#[test]
+fn supported_codes_and_btns_convert() {
+    assert!(supported_oscodes().contains(&OsCode::KEY_A));
+    assert!(!supported_oscodes().contains(&OsCode::BTN_LEFT));
+    assert_eq!(
+        supported_btns(),
+        [Btn::Left, Btn::Right, Btn::Mid, Btn::Forward, Btn::Backward]
+    );
+    let mut kbd_out = KbdOut::new_simulated();
+    for &code in supported_oscodes() {
+        kbd_out.tap_key(code).unwrap();
+    }
+    for &btn in supported_btns() {
+        kbd_out.click_btn(btn).unwrap();
+        kbd_out.release_btn(btn).unwrap();
+    }
+    assert_eq!(
+        kbd_out.drain_simulated().len(),
+        2 * (supported_oscodes().len() + supported_btns().len())
+    );
+}
+
+#[test]
+fn navigation_keys_set_e0_on_press_and_release() {
+    let navigation = [
+        OsCode::KEY_INSERT,
+        OsCode::KEY_DELETE,
+        OsCode::KEY_HOME,
+        OsCode::KEY_END,
+        OsCode::KEY_PAGEUP,
+        OsCode::KEY_PAGEDOWN,
+        OsCode::KEY_UP,
+        OsCode::KEY_DOWN,
+        OsCode::KEY_LEFT,
+        OsCode::KEY_RIGHT,
+    ];
+    let mut kbd_out = KbdOut::new_simulated();
+    for key in navigation {
+        kbd_out.press_key(key).unwrap();
+        kbd_out.release_key(key).unwrap();
+        let states: Vec<_> = kbd_out
+            .drain_simulated()
+            .into_iter()
+            .map(|event| match event {
+                OutEvent::Stroke(InputEvent(Stroke::Keyboard { state, .. })) => state,
+                other => panic!("expected a key stroke for {key:?}, got {other:?}"),
+            })
+            .collect();
+        // Without E0 these are the numpad keys with num lock off.
+        assert_eq!(
+            states,
+            [KeyState::DOWN | KeyState::E0, KeyState::UP | KeyState::E0],
+            "{key:?}"
+        );
+    }
+}
+
+#[test]
+fn oscode_scancode_mapping_round_trips() {
+    let extended = [
+        OsCode::KEY_RIGHTCTRL,
+        OsCode::KEY_RIGHTALT,
+        OsCode::KEY_LEFTMETA,
+        OsCode::KEY_RIGHTMETA,
+        OsCode::KEY_KPENTER,
+        OsCode::KEY_KPSLASH,
+        OsCode::KEY_UP,
+        OsCode::KEY_DOWN,
+        OsCode::KEY_LEFT,
+        OsCode::KEY_RIGHT,
+        OsCode::KEY_HOME,
+        OsCode::KEY_END,
+        OsCode::KEY_PAGEUP,
+        OsCode::KEY_PAGEDOWN,
+        OsCode::KEY_INSERT,
+        OsCode::KEY_DELETE,
+    ];
+    for code in (0..=u16::MAX).filter_map(OsCode::from_u16) {
+        let stroke = match Stroke::try_from(OsCodeWrapper(code)) {
+            Ok(stroke) => stroke,
+            Err(()) => continue,
+        };
+        let state = match stroke {
+            Stroke::Keyboard { state, .. } => state,
+            _ => panic!("expected keyboard stroke for {code:?}"),
+        };
+        if extended.contains(&code) {
+            assert!(state.contains(KeyState::E0), "{code:?} is not extended");
+        }
+        match OsCodeWrapper::try_from(stroke) {
+            Ok(OsCodeWrapper(back)) => assert_eq!(back, code),
+            Err(()) => panic!("{code:?} does not map back"),
+        }
+    }
+}
+
+#[test]
+fn move_in_box_clamps_to_the_edges() {
+    let mut kbd_out = KbdOut::new_simulated();
+    kbd_out.set_screen_metrics(ScreenMetrics {
+        left: 0,
+        top: 0,
+        width: 1920,
+        height: 1080,
+    });
+    let bounds = Rect {
+        left: 100,
+        top: 100,
+        width: 200,
+        height: 100,
+    };
+    kbd_out.move_mouse_to(150, 150).unwrap();
+    kbd_out.drain_simulated();
+    assert_eq!(
+        kbd_out.move_mouse_in_box(100, 0, bounds).unwrap(),
+        (250, 150)
+    );
+    // Only 49 pixels are left before the right edge.
+    assert_eq!(
+        kbd_out.move_mouse_in_box(100, 0, bounds).unwrap(),
+        (299, 150)
+    );
+    assert_eq!(
+        kbd_out.move_mouse_in_box(0, -500, bounds).unwrap(),
+        (299, 100)
+    );
+    kbd_out.flush().unwrap();
+    assert_eq!(
+        kbd_out.drain_simulated(),
+        vec![OutEvent::Stroke(InputEvent::from_mouse_move_xy(149, -50))]
+    );
+}
+
+#[test]
+fn keepalive_sends_zero_move() {
+    let mut kbd_out = KbdOut::new_simulated();
+    kbd_out.keepalive().unwrap();
+    assert_eq!(
+        kbd_out.drain_simulated(),
+        vec![OutEvent::Stroke(InputEvent::from_mouse_move_xy(0, 0))]
+    );
+    assert!(!kbd_out.has_pending_output());
+}
+
+#[test]
+fn diagonal_scroll_sends_both_wheels() {
+    let mut kbd_out = KbdOut::new_simulated();
+    for (direction, vertical, horizontal) in [
+        (MWheelDiagonal::UpLeft, 120, -120),
+        (MWheelDiagonal::UpRight, 120, 120),
+        (MWheelDiagonal::DownLeft, -120, -120),
+        (MWheelDiagonal::DownRight, -120, 120),
+    ] {
+        kbd_out.scroll_diagonal(direction, 120).unwrap();
+        let wheels: Vec<_> = kbd_out
+            .drain_simulated()
+            .into_iter()
+            .map(|event| match event {
+                OutEvent::Stroke(InputEvent(Stroke::Mouse { state, rolling, .. })) => {
+                    (state, rolling)
+                }
+                other => panic!("expected a mouse stroke, got {other:?}"),
+            })
+            .collect();
+        assert_eq!(
+            wheels,
+            vec![
+                (MouseState::WHEEL, vertical),
+                (MouseState::HWHEEL, horizontal)
+            ],
+            "{direction:?}"
+        );
+    }
+}
+
+#[test]
+fn builder_applies_settings() {
+    let mut kbd_out = KbdOutBuilder::new()
+        .simulated()
+        .invert_scroll(true)
+        .source_id(7)
+        .dpi_scale(2.0)
+        .build()
+        .unwrap();
+    kbd_out.scroll(MWheelDirection::Up, 120).unwrap();
+    kbd_out.move_mouse_xy(3, -1).unwrap();
+    kbd_out.flush().unwrap();
+    let strokes: Vec<_> = kbd_out
+        .drain_simulated()
+        .into_iter()
+        .map(|event| match event {
+            OutEvent::Stroke(InputEvent(stroke)) => stroke,
+            other => panic!("expected a stroke, got {other:?}"),
+        })
+        .collect();
+    assert!(matches!(
+        strokes[0],
+        Stroke::Mouse {
+            state: MouseState::WHEEL,
+            rolling: -120,
+            ..
+        }
+    ));
+    assert!(matches!(strokes[1], Stroke::Mouse { x: 6, y: -2, .. }));
+    assert!(strokes.iter().all(|stroke| source_id(stroke) == Some(7)));
+}
+
+#[test]
+fn play_sequence_schedules_the_delays() {
+    let mut kbd_out = KbdOut::new_simulated();
+    let press = InputEvent::from_oscode(OsCode::KEY_A, KeyValue::Press).unwrap();
+    let release = InputEvent::from_oscode(OsCode::KEY_A, KeyValue::Release).unwrap();
+    kbd_out.play_sequence(&[(press, 10), (release, 0)]).unwrap();
+    assert_eq!(kbd_out.drain_simulated(), vec![OutEvent::Stroke(press)]);
+    assert!(kbd_out.has_pending_output());
+    for _ in 0..9 {
+        kbd_out.tick().unwrap();
+    }
+    assert!(kbd_out.drain_simulated().is_empty());
+    kbd_out.tick().unwrap();
+    assert_eq!(kbd_out.drain_simulated(), vec![OutEvent::Stroke(release)]);
+    assert!(!kbd_out.has_pending_output());
+}
+
+#[test]
+fn repeat_is_sent_as_key_down() {
+    let mut kbd_out = KbdOut::new_simulated();
+    kbd_out.write_key(OsCode::KEY_A, KeyValue::Repeat).unwrap();
+    let stroke = match kbd_out.drain_simulated().as_slice() {
+        [OutEvent::Stroke(InputEvent(stroke))] => *stroke,
+        other => panic!("expected one stroke, got {other:?}"),
+    };
+    match stroke {
+        Stroke::Keyboard { state, .. } => assert!(!state.contains(KeyState::UP)),
+        _ => panic!("expected a keyboard stroke"),
+    }
+    assert!(is_repeat(&stroke));
+    assert!(kbd_out.held_keys().contains(&OsCode::KEY_A));
+    kbd_out.write_code(0x41, KeyValue::Repeat).unwrap();
+    assert_eq!(
+        kbd_out.drain_simulated(),
+        vec![OutEvent::VirtualKey {
+            code: 0x41,
+            value: KeyValue::Repeat
+        }]
+    );
+}
+
+#[test]
+#[should_panic(expected = "repeat of mouse code")]
+#[cfg(debug_assertions)]
+fn repeat_of_mouse_code_is_rejected() {
+    let mut kbd_out = KbdOut::new_simulated();
+    let _ = kbd_out.write_key(OsCode::BTN_LEFT, KeyValue::Repeat);
+}
+
+#[test]
+fn stamped_strokes_read_back_are_filtered() {
+    let mut kbd_out = KbdOut::new_simulated();
+    kbd_out.set_source_id(3);
+    kbd_out.tap_key(OsCode::KEY_A).unwrap();
+    kbd_out.scroll(MWheelDirection::Down, 120).unwrap();
+    for event in kbd_out.drain_simulated() {
+        match event {
+            // The read loop passes these through instead of remapping them.
+            OutEvent::Stroke(InputEvent(stroke)) => {
+                assert!(has_synthetic_mark(information(&stroke)));
+                assert!(is_synthetic(&stroke));
+                assert!(is_passed_through(&stroke, true));
+                assert!(is_passed_through(&stroke, false));
+            }
+            other => panic!("expected a stroke, got {other:?}"),
+        }
+    }
+    assert!(!has_synthetic_mark(0));
+    assert!(!has_synthetic_mark(KANATA_SYNTHETIC_MARK ^ 0x0001_0000));
+}
+
+#[test]
+fn physical_strokes_are_processed() {
+    let key = Stroke::Keyboard {
+        code: ScanCode::A,
+        state: KeyState::DOWN,
+        information: 0,
+    };
+    assert!(!is_passed_through(&key, false));
+    assert!(!is_passed_through(&key, true));
+    let click = Stroke::Mouse {
+        state: MouseState::LEFT_BUTTON_DOWN,
+        flags: MouseFlags::empty(),
+        rolling: 0,
+        x: 0,
+        y: 0,
+        information: 0,
+    };
+    // Mouse input is only processed when a mouse is intercepted.
+    assert!(is_passed_through(&click, false));
+    assert!(!is_passed_through(&click, true));
+    // Information that only looks like the mark in part is not kanata output.
+    let almost = Stroke::Keyboard {
+        code: ScanCode::A,
+        state: KeyState::DOWN,
+        information: KANATA_SYNTHETIC_MARK ^ 0x0001_0000,
+    };
+    assert!(!is_passed_through(&almost, true));
+}
+
+#[test]
+fn hold_repeat_sends_repeats_until_stopped() {
+    let mut kbd_out = KbdOut::new_simulated();
+    kbd_out.hold_repeat(OsCode::KEY_J, 5, 2).unwrap();
+    let key = |value| OutEvent::Stroke(InputEvent::from_oscode(OsCode::KEY_J, value).unwrap());
+    assert_eq!(kbd_out.drain_simulated(), vec![key(KeyValue::Press)]);
+    let mut repeat_ticks = vec![];
+    for tick in 1..=10 {
+        kbd_out.tick().unwrap();
+        for event in kbd_out.drain_simulated() {
+            assert_eq!(event, key(KeyValue::Repeat));
+            repeat_ticks.push(tick);
+        }
+    }
+    assert_eq!(repeat_ticks, vec![5, 7, 9]);
+    kbd_out.stop_repeat(OsCode::KEY_J).unwrap();
+    assert_eq!(kbd_out.drain_simulated(), vec![key(KeyValue::Release)]);
+    for _ in 0..10 {
+        kbd_out.tick().unwrap();
+    }
+    assert!(kbd_out.drain_simulated().is_empty());
+}
+
+#[test]
+fn reset_cancels_hold_repeat() {
+    let mut kbd_out = KbdOut::new_simulated();
+    kbd_out.hold_repeat(OsCode::KEY_J, 1, 1).unwrap();
+    kbd_out.reset().unwrap();
+    assert_eq!(
+        kbd_out.drain_simulated(),
+        vec![
+            OutEvent::Stroke(InputEvent::from_oscode(OsCode::KEY_J, KeyValue::Press).unwrap()),
+            OutEvent::Stroke(InputEvent::from_oscode(OsCode::KEY_J, KeyValue::Release).unwrap()),
+        ]
+    );
+    for _ in 0..5 {
+        kbd_out.tick().unwrap();
+    }
+    assert!(kbd_out.drain_simulated().is_empty());
+}
+
+#[test]
+fn channel_receives_output_of_a_moved_handle() {
+    let (tx, rx) = std::sync::mpsc::channel();
+    let sender = KbdOutSender::spawn(KbdOut::new_with_channel(tx));
+    sender.tap_key(OsCode::KEY_Z).unwrap();
+    sender.click_btn(Btn::Left).unwrap();
+    let key = |value| OutEvent::Stroke(InputEvent::from_oscode(OsCode::KEY_Z, value).unwrap());
+    let received: Vec<_> = (0..3)
+        .map(|_| rx.recv_timeout(Duration::from_secs(5)).unwrap())
+        .collect();
+    assert_eq!(
+        received,
+        vec![
+            key(KeyValue::Press),
+            key(KeyValue::Release),
+            OutEvent::Stroke(InputEvent::from_mouse_btn(Btn::Left, false).unwrap()),
+        ]
+    );
+    drop(sender.shutdown());
+    assert!(rx.recv().is_err());
+}
+
+#[test]
+fn drag_holds_the_button_across_the_moves() {
+    let mut kbd_out = KbdOut::new_simulated();
+    kbd_out.drag(Btn::Mid, 30, -9, 3).unwrap();
+    let btn = |is_up| OutEvent::Stroke(InputEvent::from_mouse_btn(Btn::Mid, is_up).unwrap());
+    let mv = |dx, dy| OutEvent::Stroke(InputEvent::from_mouse_move_xy(dx, dy));
+    assert_eq!(
+        kbd_out.drain_simulated(),
+        vec![btn(false), mv(10, -3), mv(10, -3), mv(10, -3), btn(true)]
+    );
+    assert!(kbd_out.held_btns.is_empty());
+}
+
+#[test]
+fn wheel_autoscroll_toggles_the_middle_button() {
+    let mut kbd_out = KbdOut::new_simulated();
+    let btn = |is_up| OutEvent::Stroke(InputEvent::from_mouse_btn(Btn::Mid, is_up).unwrap());
+    kbd_out.wheel_autoscroll(true).unwrap();
+    kbd_out.wheel_autoscroll(true).unwrap();
+    assert_eq!(kbd_out.drain_simulated(), vec![btn(false)]);
+    assert!(kbd_out.held_btns.contains(&Btn::Mid));
+    kbd_out.wheel_autoscroll(false).unwrap();
+    kbd_out.wheel_autoscroll(false).unwrap();
+    assert_eq!(kbd_out.drain_simulated(), vec![btn(true)]);
+    assert!(kbd_out.held_btns.is_empty());
+}
+
+#[test]
+fn unicode_method_selects_the_output() {
+    let vk = |code, value| OutEvent::VirtualKey { code, value };
+    let packets = |c: char| -> Vec<_> {
+        unicode_packets(c)
+            .map(|(unit, up)| OutEvent::Unicode { unit, up })
+            .collect()
+    };
+    let mut kbd_out = KbdOut::new_simulated();
+    kbd_out.send_unicode('a').unwrap();
+    assert_eq!(kbd_out.drain_simulated(), packets('a'));
+
+    kbd_out.set_unicode_method(UnicodeMethod::KeyScan);
+    kbd_out.send_unicode_string("aA").unwrap();
+    assert_eq!(
+        kbd_out.drain_simulated(),
+        vec![
+            vk(0x41, KeyValue::Press),
+            vk(0x41, KeyValue::Release),
+            vk(0x10, KeyValue::Press),
+            vk(0x41, KeyValue::Press),
+            vk(0x41, KeyValue::Release),
+            vk(0x10, KeyValue::Release),
+        ]
+    );
+    // A character outside the BMP has no key on any layout.
+    kbd_out.send_unicode('😀').unwrap();
+    assert_eq!(kbd_out.drain_simulated(), packets('😀'));
+    assert!(kbd_out.held_codes.is_empty());
+}
+
+#[test]
+fn errors_are_typed() {
+    let mut kbd_out = KbdOut::new_simulated();
+    assert!(matches!(
+        kbd_out.try_write_key(OsCode::BTN_LEFT, KeyValue::Press),
+        Err(KbdOutError::Unmapped(OsCodeMappingError(OsCode::BTN_LEFT)))
+    ));
+    assert!(matches!(
+        kbd_out.press_key_named("nope"),
+        Err(KbdOutError::InvalidInput(_))
+    ));
+    assert!(kbd_out.drain_simulated().is_empty());
+}
+
+#[test]
+fn errors_convert_to_io_errors() {
+    let cases = [
+        (
+            KbdOutError::Unmapped(OsCodeMappingError(OsCode::BTN_LEFT)),
+            io::ErrorKind::InvalidInput,
+        ),
+        (
+            KbdOutError::InvalidInput("bad".into()),
+            io::ErrorKind::InvalidInput,
+        ),
+        (
+            KbdOutError::Unsupported("no".into()),
+            io::ErrorKind::Unsupported,
+        ),
+        (
+            KbdOutError::Clipboard(io::Error::from(io::ErrorKind::PermissionDenied)),
+            io::ErrorKind::PermissionDenied,
+        ),
+        (KbdOutError::ChannelClosed, io::ErrorKind::BrokenPipe),
+        (
+            KbdOutError::Io(io::Error::from(io::ErrorKind::TimedOut)),
+            io::ErrorKind::TimedOut,
+        ),
+    ];
+    for (e, kind) in cases {
+        assert_eq!(e.kind(), kind, "{e}");
+        let message = e.to_string();
+        let io_err = io::Error::from(e);
+        assert_eq!(io_err.kind(), kind);
+        assert!(io_err.to_string().contains(&message), "{io_err}");
+    }
+}
+
+#[test]
+fn tap_then_type_taps_the_trigger_before_the_text() {
+    let mut kbd_out = KbdOut::new_simulated();
+    kbd_out.tap_then_type(OsCode::KEY_TAB, "hI😀").unwrap();
+    let key = |key, value| OutEvent::Stroke(InputEvent::from_oscode(key, value).unwrap());
+    let mut expected = vec![
+        key(OsCode::KEY_TAB, KeyValue::Press),
+        key(OsCode::KEY_TAB, KeyValue::Release),
+        key(OsCode::KEY_H, KeyValue::Press),
+        key(OsCode::KEY_H, KeyValue::Release),
+        key(OsCode::KEY_LEFTSHIFT, KeyValue::Press),
+        key(OsCode::KEY_I, KeyValue::Press),
+        key(OsCode::KEY_I, KeyValue::Release),
+        key(OsCode::KEY_LEFTSHIFT, KeyValue::Release),
+    ];
+    expected.extend(unicode_string_packets("😀").map(|(unit, up)| OutEvent::Unicode { unit, up }));
+    assert_eq!(kbd_out.drain_simulated(), expected);
+    assert!(!kbd_out.has_pending_output());
+}
+
+#[test]
+fn button_watchdog_releases_stuck_buttons() {
+    let mut kbd_out = KbdOut::new_simulated();
+    let btn = |btn, is_up| OutEvent::Stroke(InputEvent::from_mouse_btn(btn, is_up).unwrap());
+    kbd_out.click_btn(Btn::Left).unwrap();
+    // Off by default.
+    for _ in 0..20 {
+        kbd_out.tick().unwrap();
+    }
+    assert!(kbd_out.held_btns.contains(&Btn::Left));
+    assert!(!kbd_out.has_pending_output());
+
+    kbd_out.set_button_watchdog(Some(Duration::from_millis(10)));
+    assert!(kbd_out.has_pending_output());
+    kbd_out.click_btn(Btn::Right).unwrap();
+    kbd_out.tick().unwrap();
+    assert_eq!(
+        kbd_out.drain_simulated(),
+        vec![
+            btn(Btn::Left, false),
+            btn(Btn::Right, false),
+            btn(Btn::Left, true)
+        ]
+    );
+    for _ in 0..8 {
+        kbd_out.tick().unwrap();
+    }
+    // Refreshing the button restarts its timeout.
+    kbd_out.click_btn(Btn::Right).unwrap();
+    for _ in 0..9 {
+        kbd_out.tick().unwrap();
+    }
+    assert!(kbd_out.drain_simulated().is_empty());
+    kbd_out.tick().unwrap();
+    assert_eq!(kbd_out.drain_simulated(), vec![btn(Btn::Right, true)]);
+    assert!(kbd_out.held_btns.is_empty());
+    assert!(!kbd_out.has_pending_output());
+}
+
+#[test]
+fn getters_report_the_builder_options() {
+    let defaults = KbdOut::new_simulated();
+    assert!(!defaults.is_scroll_inverted());
+    assert_eq!(defaults.dpi_scale(), 1.0);
+    assert_eq!(defaults.source_id(), 0);
+    assert!(defaults.device().is_none());
+    assert!(!defaults.is_suspended());
+
+    let kbd_out = KbdOutBuilder::new()
+        .simulated()
+        .device(12)
+        .invert_scroll(true)
+        .scroll_accumulation(true)
+        .dpi_scale(1.25)
+        .move_step(4)
+        .mouse_rate_limit(Some(Duration::from_millis(8)))
+        .button_watchdog(Some(Duration::from_secs(5)))
+        .source_id(7)
+        .unicode_char_delay(Some(3))
+        .unicode_method(UnicodeMethod::KeyScan)
+        .tap_gap(6)
+        .skip_unheld_releases(true)
+        .skip_held_clicks(false)
+        .swap_nav_buttons(true)
+        .build()
+        .unwrap();
+    assert_eq!(kbd_out.device(), Some(12));
+    assert!(kbd_out.is_scroll_inverted());
+    assert!(kbd_out.scroll_accumulation());
+    assert_eq!(kbd_out.dpi_scale(), 1.25);
+    assert_eq!(kbd_out.move_step(), 4);
+    assert_eq!(kbd_out.mouse_rate_limit(), Some(Duration::from_millis(8)));
+    assert_eq!(kbd_out.button_watchdog(), Some(Duration::from_secs(5)));
+    assert_eq!(kbd_out.source_id(), 7);
+    assert_eq!(kbd_out.unicode_char_delay_ms(), Some(3));
+    assert_eq!(kbd_out.unicode_method(), UnicodeMethod::KeyScan);
+    assert_eq!(kbd_out.tap_gap(), 6);
+    assert!(kbd_out.skip_unheld_releases());
+    assert!(!kbd_out.skip_held_clicks());
+    assert!(kbd_out.swap_nav_buttons());
+    assert!(kbd_out.paste_preserves_clipboard());
+    assert_eq!(
+        kbd_out.max_scroll_distances(),
+        (DEFAULT_MAX_SCROLL_DISTANCE, DEFAULT_MAX_SCROLL_DISTANCE)
+    );
+}
+
+#[test]
+fn virtual_position_follows_relative_moves() {
+    let mut kbd_out = KbdOut::new_simulated();
+    // A simulated output has no OS cursor to seed from.
+    assert_eq!(kbd_out.virtual_position(), (0, 0));
+    kbd_out.move_mouse_xy(10, -5).unwrap();
+    kbd_out.flush().unwrap();
+    assert_eq!(kbd_out.virtual_position(), (10, -5));
+
+    kbd_out.set_screen_metrics(ScreenMetrics {
+        left: -1920,
+        top: 0,
+        width: 3840,
+        height: 1080,
+    });
+    kbd_out.move_mouse_to(100, 500).unwrap();
+    assert_eq!(kbd_out.virtual_position(), (100, 500));
+    kbd_out.move_mouse_xy(-30, 20).unwrap();
+    kbd_out.flush().unwrap();
+    kbd_out.move_mouse_smooth(40, 0, 4).unwrap();
+    assert_eq!(kbd_out.virtual_position(), (110, 520));
+    // The cursor stops at the edges of the screen.
+    kbd_out.move_mouse_smooth(0, 2000, 1).unwrap();
+    assert_eq!(kbd_out.virtual_position(), (110, 1079));
+
+    kbd_out.set_mouse(0, 0).unwrap();
+    assert_eq!(kbd_out.virtual_position(), (0, 0));
+    kbd_out.move_mouse_to(-1000, 0).unwrap();
+    kbd_out.move_mouse_xy(5, 5).unwrap();
+    kbd_out.flush().unwrap();
+    assert_eq!(kbd_out.resync_virtual_position(), (-1000, 0));
+}
+
+#[test]
+fn unicode_char_delay_queues_characters_on_the_tick_clock() {
+    let packets = |c: char| -> Vec<_> {
+        unicode_packets(c)
+            .map(|(unit, up)| OutEvent::Unicode { unit, up })
+            .collect()
+    };
+    let mut kbd_out = KbdOut::new_simulated();
+    kbd_out.set_unicode_char_delay(Some(2));
+    kbd_out.send_unicode_string("ab").unwrap();
+    // Queued behind the string, not sent right away.
+    kbd_out.send_unicode('😀').unwrap();
+    assert_eq!(kbd_out.drain_simulated(), packets('a'));
+    kbd_out.tick().unwrap();
+    assert!(kbd_out.drain_simulated().is_empty());
+    kbd_out.tick().unwrap();
+    assert_eq!(kbd_out.drain_simulated(), packets('b'));
+    kbd_out.tick().unwrap();
+    kbd_out.tick().unwrap();
+    assert_eq!(kbd_out.drain_simulated(), packets('😀'));
+    assert!(!kbd_out.has_pending_output());
+
+    // Once the delay has passed since the last character, the next one goes out right away.
+    kbd_out.tick().unwrap();
+    kbd_out.tick().unwrap();
+    kbd_out.send_unicode('c').unwrap();
+    assert_eq!(kbd_out.drain_simulated(), packets('c'));
+}
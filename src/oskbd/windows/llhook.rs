@@ -13,7 +13,7 @@ use winapi::shared::windef::*;
 use winapi::um::winuser::*;
 
 use crate::kanata::CalculatedMouseMove;
-use crate::oskbd::{KeyEvent, KeyValue};
+use crate::oskbd::{KeyEvent, KeyValue, KANATA_SYNTHETIC_MARK};
 use kanata_parser::custom_action::*;
 use kanata_parser::keys::*;
 
@@ -192,6 +192,9 @@ impl KbdOut {
             Btn::Mid => send_btn(MOUSEEVENTF_MIDDLEDOWN),
             Btn::Backward => send_xbtn(MOUSEEVENTF_XDOWN, XBUTTON1),
             Btn::Forward => send_xbtn(MOUSEEVENTF_XDOWN, XBUTTON2),
+            Btn::Mouse6 | Btn::Mouse7 => {
+                log::warn!("{btn:?} is not supported by Windows SendInput, ignoring");
+            }
         };
         Ok(())
     }
@@ -204,6 +207,9 @@ impl KbdOut {
             Btn::Mid => send_btn(MOUSEEVENTF_MIDDLEUP),
             Btn::Backward => send_xbtn(MOUSEEVENTF_XUP, XBUTTON1),
             Btn::Forward => send_xbtn(MOUSEEVENTF_XUP, XBUTTON2),
+            Btn::Mouse6 | Btn::Mouse7 => {
+                log::warn!("{btn:?} is not supported by Windows SendInput, ignoring");
+            }
         };
         Ok(())
     }
@@ -241,6 +247,7 @@ fn send_btn(flag: u32) {
 
         // set button
         let mut m_input: MOUSEINPUT = mem::zeroed();
+        m_input.dwExtraInfo = KANATA_SYNTHETIC_MARK as usize;
         m_input.dwFlags |= flag;
 
         *inputs[0].u.mi_mut() = m_input;
@@ -255,6 +262,7 @@ fn send_xbtn(flag: u32, xbtn: u16) {
 
         // set button
         let mut m_input: MOUSEINPUT = mem::zeroed();
+        m_input.dwExtraInfo = KANATA_SYNTHETIC_MARK as usize;
         m_input.dwFlags |= flag;
         m_input.mouseData = xbtn.into();
 
@@ -269,6 +277,7 @@ fn scroll(direction: MWheelDirection, distance: u16) {
         inputs[0].type_ = INPUT_MOUSE;
 
         let mut m_input: MOUSEINPUT = mem::zeroed();
+        m_input.dwExtraInfo = KANATA_SYNTHETIC_MARK as usize;
         m_input.dwFlags |= MOUSEEVENTF_WHEEL;
         m_input.mouseData = match direction {
             MWheelDirection::Up => distance.into(),
@@ -287,6 +296,7 @@ fn hscroll(direction: MWheelDirection, distance: u16) {
         inputs[0].type_ = INPUT_MOUSE;
 
         let mut m_input: MOUSEINPUT = mem::zeroed();
+        m_input.dwExtraInfo = KANATA_SYNTHETIC_MARK as usize;
         m_input.dwFlags |= MOUSEEVENTF_HWHEEL;
         m_input.mouseData = match direction {
             MWheelDirection::Right => distance.into(),
@@ -349,7 +359,7 @@ fn mouse_event(flags: u32, data: u32, dx: i32, dy: i32) {
                 mouseData: data,
                 dwFlags: flags,
                 time: 0,
-                dwExtraInfo: 0,
+                dwExtraInfo: KANATA_SYNTHETIC_MARK as usize,
             })
         },
     };
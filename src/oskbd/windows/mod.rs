@@ -1,10 +1,15 @@
 use std::mem;
 
+#[cfg(feature = "interception_driver")]
+use winapi::shared::windef::POINT;
+#[cfg(feature = "interception_driver")]
+use winapi::um::winbase::{GlobalAlloc, GlobalFree, GlobalLock, GlobalUnlock, GMEM_MOVEABLE};
 use winapi::um::winuser::*;
 
+#[cfg(not(feature = "interception_driver"))]
 use encode_unicode::CharExt;
 
-use crate::oskbd::KeyValue;
+use crate::oskbd::{KeyValue, KANATA_SYNTHETIC_MARK};
 
 #[cfg(not(feature = "interception_driver"))]
 mod llhook;
@@ -22,6 +27,7 @@ pub use interception_convert::*;
 
 pub const HI_RES_SCROLL_UNITS_IN_LO_RES: u16 = 120;
 
+#[cfg(not(feature = "interception_driver"))]
 fn send_uc(c: char, up: bool) {
     log::debug!("sending unicode {c}");
     let mut inputs: [INPUT; 2] = unsafe { mem::zeroed() };
@@ -33,6 +39,7 @@ fn send_uc(c: char, up: bool) {
             let mut kb_input: KEYBDINPUT = unsafe { mem::zeroed() };
             kb_input.wScan = c;
             kb_input.dwFlags |= KEYEVENTF_UNICODE;
+            kb_input.dwExtraInfo = KANATA_SYNTHETIC_MARK as usize;
             if up {
                 kb_input.dwFlags |= KEYEVENTF_KEYUP;
             }
@@ -50,6 +57,155 @@ fn send_uc(c: char, up: bool) {
     }
 }
 
+/// Send a single UTF-16 code unit as a VK_PACKET key event.
+#[cfg(feature = "interception_driver")]
+fn send_uc_unit(unit: u16, up: bool) -> Result<(), std::io::Error> {
+    log::debug!("sending unicode code unit {unit:#06x}");
+    unsafe {
+        let mut kb_input: KEYBDINPUT = mem::zeroed();
+        kb_input.wScan = unit;
+        kb_input.dwFlags |= KEYEVENTF_UNICODE;
+        kb_input.dwExtraInfo = KANATA_SYNTHETIC_MARK as usize;
+        if up {
+            kb_input.dwFlags |= KEYEVENTF_KEYUP;
+        }
+
+        let mut inputs: [INPUT; 1] = mem::zeroed();
+        inputs[0].type_ = INPUT_KEYBOARD;
+        *inputs[0].u.ki_mut() = kb_input;
+        if SendInput(1, inputs.as_mut_ptr(), mem::size_of::<INPUT>() as _) == 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Returns the left and top coordinates and the width and height in pixels of the virtual
+/// screen, i.e. the bounding rectangle of all monitors.
+#[cfg(feature = "interception_driver")]
+fn virtual_screen_rect() -> (i32, i32, u32, u32) {
+    let (x, y, w, h) = unsafe {
+        (
+            GetSystemMetrics(SM_XVIRTUALSCREEN),
+            GetSystemMetrics(SM_YVIRTUALSCREEN),
+            GetSystemMetrics(SM_CXVIRTUALSCREEN),
+            GetSystemMetrics(SM_CYVIRTUALSCREEN),
+        )
+    };
+    (
+        x,
+        y,
+        u32::try_from(w).unwrap_or_default(),
+        u32::try_from(h).unwrap_or_default(),
+    )
+}
+
+/// Returns the position of the cursor in virtual desktop coordinates, or `None` if the OS does
+/// not report it, e.g. while the secure desktop is shown.
+#[cfg(feature = "interception_driver")]
+fn cursor_pos() -> Option<(i32, i32)> {
+    let mut point = POINT { x: 0, y: 0 };
+    match unsafe { GetCursorPos(&mut point) } {
+        0 => None,
+        _ => Some((point.x, point.y)),
+    }
+}
+
+/// Returns the maximum time in milliseconds between two clicks for the OS to treat them as a
+/// double-click.
+#[cfg(feature = "interception_driver")]
+fn double_click_time_ms() -> u32 {
+    unsafe { GetDoubleClickTime() }
+}
+
+/// Returns the virtual key that types the UTF-16 code unit `unit` on the current keyboard layout,
+/// and the modifiers it needs as bits: 1 for shift, 2 for ctrl and 4 for alt. `None` if no key
+/// on the layout types it.
+#[cfg(feature = "interception_driver")]
+fn vk_key_scan(unit: u16) -> Option<(u16, u8)> {
+    // Both bytes are -1 if there is no such key.
+    let res = unsafe { VkKeyScanW(unit) } as u16;
+    if res == 0xffff {
+        return None;
+    }
+    Some((res & 0xff, (res >> 8) as u8))
+}
+
+/// Holds the clipboard open and closes it on drop.
+#[cfg(feature = "interception_driver")]
+struct OpenClipboardGuard;
+
+#[cfg(feature = "interception_driver")]
+impl OpenClipboardGuard {
+    fn open() -> Result<Self, std::io::Error> {
+        if unsafe { OpenClipboard(std::ptr::null_mut()) } == 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(Self)
+    }
+}
+
+#[cfg(feature = "interception_driver")]
+impl Drop for OpenClipboardGuard {
+    fn drop(&mut self) {
+        unsafe { CloseClipboard() };
+    }
+}
+
+/// Returns the text on the clipboard, or `None` if it does not hold text.
+#[cfg(feature = "interception_driver")]
+fn clipboard_text() -> Result<Option<String>, std::io::Error> {
+    let _clipboard = OpenClipboardGuard::open()?;
+    unsafe {
+        let handle = GetClipboardData(CF_UNICODETEXT);
+        if handle.is_null() {
+            return Ok(None);
+        }
+        let data = GlobalLock(handle) as *const u16;
+        if data.is_null() {
+            return Err(std::io::Error::last_os_error());
+        }
+        let mut len = 0;
+        while *data.add(len) != 0 {
+            len += 1;
+        }
+        let text = String::from_utf16_lossy(std::slice::from_raw_parts(data, len));
+        GlobalUnlock(handle);
+        Ok(Some(text))
+    }
+}
+
+/// Replaces the contents of the clipboard with `text`.
+#[cfg(feature = "interception_driver")]
+fn set_clipboard_text(text: &str) -> Result<(), std::io::Error> {
+    let units: Vec<u16> = text.encode_utf16().chain(Some(0)).collect();
+    let _clipboard = OpenClipboardGuard::open()?;
+    unsafe {
+        if EmptyClipboard() == 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let handle = GlobalAlloc(GMEM_MOVEABLE, units.len() * mem::size_of::<u16>());
+        if handle.is_null() {
+            return Err(std::io::Error::last_os_error());
+        }
+        let data = GlobalLock(handle) as *mut u16;
+        if data.is_null() {
+            let err = std::io::Error::last_os_error();
+            GlobalFree(handle);
+            return Err(err);
+        }
+        std::ptr::copy_nonoverlapping(units.as_ptr(), data, units.len());
+        GlobalUnlock(handle);
+        // On success the clipboard owns the memory.
+        if SetClipboardData(CF_UNICODETEXT, handle).is_null() {
+            let err = std::io::Error::last_os_error();
+            GlobalFree(handle);
+            return Err(err);
+        }
+    }
+    Ok(())
+}
+
 fn write_code(code: u16, value: KeyValue) -> Result<(), std::io::Error> {
     send_key_sendinput(
         code,
@@ -69,6 +225,7 @@ fn send_key_sendinput(code: u16, is_key_up: bool) {
             kb_input.dwFlags |= KEYEVENTF_KEYUP;
         }
         kb_input.wVk = code;
+        kb_input.dwExtraInfo = KANATA_SYNTHETIC_MARK as usize;
 
         let mut inputs: [INPUT; 1] = mem::zeroed();
         inputs[0].type_ = INPUT_KEYBOARD;